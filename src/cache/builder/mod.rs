@@ -0,0 +1,391 @@
+#[cfg(test)]
+mod test;
+
+use super::{
+    CachedJWKS, CircuitHook, ErrorHook, Hooks, JwksSource, RefreshWindow, RetryPolicy,
+    RetrySchedule, TasksOwner, TimeoutSpec, TtlSpec, UpdateHook,
+};
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerSpec, CircuitState};
+use crate::clock::{Clock, SystemClock};
+use crate::runtime::{self, BackgroundTasks, Runtime};
+use crate::state::JwksSnapshot;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// Format of the keys served at the URL
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeysFormat {
+    /// JWK Set
+    #[default]
+    JwkSet,
+    /// Map of key IDs to PEM encoded X.509 certificates of RSA public keys
+    PemCertificates,
+}
+
+/// Invalid configuration of the cache
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("JWKS URL is missing")]
+    MissingUrl,
+    #[error("Invalid JWKS URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("Could not build HTTP client: {0}")]
+    HttpClient(#[from] reqwest::Error),
+    #[error(
+        "Refresh period {period:?} before expiry should be greater than timeout deadline {deadline:?}"
+    )]
+    RefreshPeriodWithinDeadline {
+        period: Duration,
+        deadline: Duration,
+    },
+    #[error("Refresh lifetime fraction {0} should be within 0.0..=1.0")]
+    InvalidRefreshFraction(f64),
+    #[error("Refresh jitter {0} should be within 0.0..=1.0")]
+    InvalidRefreshJitter(f64),
+    #[error("Minimal TTL {min:?} should not be greater than maximal TTL {max:?}")]
+    InvalidTtlBounds { min: Duration, max: Duration },
+    #[error("Timeout deadline should be greater than zero")]
+    ZeroDeadline,
+    #[error("Initial backoff {initial:?} should not be greater than maximal backoff {max:?}")]
+    InvalidBackoff { initial: Duration, max: Duration },
+    #[error("Initial retry delay {initial:?} should not be greater than maximal delay {max:?}")]
+    InvalidRetrySchedule { initial: Duration, max: Duration },
+    #[error("Negative cache capacity should be greater than zero")]
+    ZeroNegativeCacheCapacity,
+    #[error("Negative cache is only filled by refreshes on unknown key ID, which are not enabled")]
+    NegativeCacheWithoutRefresh,
+    #[error("Circuit breaker failure threshold should be greater than zero")]
+    ZeroFailureThreshold,
+    #[error(
+        "No runtime to run background work of the cache on, build it within a tokio runtime or provide one"
+    )]
+    NoRuntime,
+}
+
+/// Source of [`CachedJWKSBuilder`] until another one is given, `reqwest::Client` with default
+/// settings which is only built by [`CachedJWKSBuilder::build`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultHttpClient;
+
+/// Builder of [`CachedJWKS`], see [`CachedJWKS::builder`]
+pub struct CachedJWKSBuilder<S = DefaultHttpClient> {
+    url: Option<Result<Url, url::ParseError>>,
+    format: KeysFormat,
+    refresh_window: RefreshWindow,
+    refresh_jitter: f64,
+    max_stale: Duration,
+    ttl_spec: TtlSpec,
+    timeout_spec: TimeoutSpec,
+    retry_policy: Option<RetryPolicy>,
+    retry_schedule: RetrySchedule,
+    unknown_kid_refresh: Option<Duration>,
+    negative_cache: Option<(Duration, usize)>,
+    circuit_breaker: Option<CircuitBreakerSpec>,
+    runtime: Option<Arc<dyn Runtime>>,
+    clock: Arc<dyn Clock>,
+    hooks: Hooks,
+    source: S,
+}
+
+impl Default for CachedJWKSBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CachedJWKSBuilder {
+    /// Builder fetching JWK Set with default settings of `reqwest::Client`, refreshing it once 80% of
+    /// its lifetime has passed
+    pub fn new() -> Self {
+        Self {
+            url: None,
+            format: KeysFormat::default(),
+            refresh_window: RefreshWindow::AfterLifetimeFraction(0.8),
+            refresh_jitter: 0.0,
+            max_stale: Duration::ZERO,
+            ttl_spec: Default::default(),
+            timeout_spec: Default::default(),
+            retry_policy: None,
+            retry_schedule: Default::default(),
+            unknown_kid_refresh: None,
+            negative_cache: None,
+            circuit_breaker: None,
+            runtime: None,
+            clock: Arc::new(SystemClock),
+            hooks: Default::default(),
+            source: DefaultHttpClient,
+        }
+    }
+
+    pub fn build(self) -> Result<CachedJWKS<reqwest::Client>, ConfigError> {
+        let source = reqwest::Client::builder().build()?;
+
+        self.source(source).build()
+    }
+}
+
+impl<S> CachedJWKSBuilder<S> {
+    /// URL to fetch keys from, invalid URL is reported by [`CachedJWKSBuilder::build`]
+    pub fn url(mut self, url: impl AsRef<str>) -> Self {
+        self.url = Some(Url::parse(url.as_ref()));
+        self
+    }
+
+    /// Format of the keys served at the URL, JWK Set by default
+    pub fn format(mut self, format: KeysFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// When to start refreshing JWK Set in the background. When the source sends
+    /// `stale-while-revalidate` Cache-Control directive, JWK Set is instead refreshed once expired,
+    /// while it is still served for as long as the directive allows.
+    pub fn refresh_window(mut self, refresh_window: RefreshWindow) -> Self {
+        self.refresh_window = refresh_window;
+        self
+    }
+
+    /// Start refreshing earlier by a random part of up to `jitter` fraction of the JWK Set lifetime,
+    /// so many instances sharing the same source do not refresh all at once
+    pub fn refresh_jitter(mut self, jitter: f64) -> Self {
+        self.refresh_jitter = jitter;
+        self
+    }
+
+    /// Keep serving expired JWK Set for up to `max_stale` past its expiration when refreshing it fails,
+    /// while retrying in the background. `stale-if-error` Cache-Control directive sent by the source
+    /// extends this window.
+    pub fn max_stale(mut self, max_stale: Duration) -> Self {
+        self.max_stale = max_stale;
+        self
+    }
+
+    /// Bound lifetime of the JWK Set reported by the source
+    pub fn ttl_spec(mut self, ttl_spec: TtlSpec) -> Self {
+        self.ttl_spec = ttl_spec;
+        self
+    }
+
+    /// Timeouts and retries of a single fetch, retries are governed by the retry policy instead if
+    /// one is given
+    pub fn timeout_spec(mut self, timeout_spec: TimeoutSpec) -> Self {
+        self.timeout_spec = timeout_spec;
+        self
+    }
+
+    /// How to retry failed attempts within a single fetch, replaces retries and fixed backoff of the
+    /// timeout spec
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// How often to retry refreshing after failures, while cached JWK Set can still be served
+    pub fn retry_schedule(mut self, retry_schedule: RetrySchedule) -> Self {
+        self.retry_schedule = retry_schedule;
+        self
+    }
+
+    /// Refresh JWK Set ahead of its expiration when looked up key ID is not in it, e.g. because keys were
+    /// rotated. Such refreshes are performed at most once per `min_interval`, so lookups of made up
+    /// key IDs can not flood the source.
+    pub fn refresh_on_unknown_kid(mut self, min_interval: Duration) -> Self {
+        self.unknown_kid_refresh = Some(min_interval);
+        self
+    }
+
+    /// Remember up to `capacity` key IDs missing from a freshly fetched JWK Set for `ttl`, so their
+    /// lookups are rejected without refreshing. Remembered key IDs are forgotten once a new JWK Set is
+    /// fetched. Requires [`CachedJWKSBuilder::refresh_on_unknown_kid`], whose refreshes fill it.
+    pub fn negative_cache(mut self, ttl: Duration, capacity: usize) -> Self {
+        self.negative_cache = Some((ttl, capacity));
+        self
+    }
+
+    /// Stop calling the source once it keeps failing, fetches fail right away while the circuit is
+    /// open, or serve stale JWK Set if it is allowed to. Every failed attempt counts as a failure,
+    /// whether it timed out or the source answered with an error, permanent ones included.
+    pub fn circuit_breaker(mut self, spec: CircuitBreakerSpec) -> Self {
+        self.circuit_breaker = Some(spec);
+        self
+    }
+
+    /// Run background work of the cache on the runtime, instead of the tokio runtime it is built within
+    pub fn runtime(mut self, runtime: impl Runtime) -> Self {
+        self.runtime = Some(Arc::new(runtime));
+        self
+    }
+
+    /// Tell time by the clock, instead of the system clock
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Called with the new content whenever a JWK Set gets fetched or refreshed
+    pub fn on_update(mut self, hook: impl Fn(&JwksSnapshot) + Send + Sync + 'static) -> Self {
+        self.hooks.on_update = Some(Arc::new(hook) as UpdateHook);
+        self
+    }
+
+    /// Called with the error whenever fetching or refreshing JWK Set fails, after all retries
+    pub fn on_error(
+        mut self,
+        hook: impl Fn(&dyn core::fmt::Debug) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.on_error = Some(Arc::new(hook) as ErrorHook);
+        self
+    }
+
+    /// Called with the new state whenever the circuit breaker changes it
+    pub fn on_circuit_change(
+        mut self,
+        hook: impl Fn(CircuitState) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.on_circuit_change = Some(Arc::new(hook) as CircuitHook);
+        self
+    }
+
+    /// Fetch JWK Set with the HTTP client, instead of one with default settings
+    pub fn http_client(self, client: reqwest::Client) -> CachedJWKSBuilder<reqwest::Client> {
+        self.source(client)
+    }
+
+    /// Fetch JWK Set from the source, instead of over HTTP
+    pub fn source<T: JwksSource>(self, source: T) -> CachedJWKSBuilder<T> {
+        CachedJWKSBuilder {
+            url: self.url,
+            format: self.format,
+            refresh_window: self.refresh_window,
+            refresh_jitter: self.refresh_jitter,
+            max_stale: self.max_stale,
+            ttl_spec: self.ttl_spec,
+            timeout_spec: self.timeout_spec,
+            retry_policy: self.retry_policy,
+            retry_schedule: self.retry_schedule,
+            unknown_kid_refresh: self.unknown_kid_refresh,
+            negative_cache: self.negative_cache,
+            circuit_breaker: self.circuit_breaker,
+            runtime: self.runtime,
+            clock: self.clock,
+            hooks: self.hooks,
+            source,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let unit_range = 0.0..=1.0;
+
+        match self.refresh_window {
+            RefreshWindow::BeforeExpiry(period) if period <= self.timeout_spec.deadline => {
+                return Err(ConfigError::RefreshPeriodWithinDeadline {
+                    period,
+                    deadline: self.timeout_spec.deadline,
+                });
+            }
+            RefreshWindow::AfterLifetimeFraction(fraction) if !unit_range.contains(&fraction) => {
+                return Err(ConfigError::InvalidRefreshFraction(fraction));
+            }
+            _ => {}
+        }
+
+        if !unit_range.contains(&self.refresh_jitter) {
+            return Err(ConfigError::InvalidRefreshJitter(self.refresh_jitter));
+        }
+
+        if self.ttl_spec.min_ttl > self.ttl_spec.max_ttl {
+            return Err(ConfigError::InvalidTtlBounds {
+                min: self.ttl_spec.min_ttl,
+                max: self.ttl_spec.max_ttl,
+            });
+        }
+
+        if self.timeout_spec.deadline.is_zero() {
+            return Err(ConfigError::ZeroDeadline);
+        }
+
+        if let Some(policy) = self
+            .retry_policy
+            .filter(|policy| policy.initial_backoff > policy.max_backoff)
+        {
+            return Err(ConfigError::InvalidBackoff {
+                initial: policy.initial_backoff,
+                max: policy.max_backoff,
+            });
+        }
+
+        if self.retry_schedule.initial > self.retry_schedule.max {
+            return Err(ConfigError::InvalidRetrySchedule {
+                initial: self.retry_schedule.initial,
+                max: self.retry_schedule.max,
+            });
+        }
+
+        if self
+            .negative_cache
+            .is_some_and(|(_, capacity)| capacity == 0)
+        {
+            return Err(ConfigError::ZeroNegativeCacheCapacity);
+        }
+
+        if self.negative_cache.is_some() && self.unknown_kid_refresh.is_none() {
+            return Err(ConfigError::NegativeCacheWithoutRefresh);
+        }
+
+        if self
+            .circuit_breaker
+            .is_some_and(|spec| spec.failure_threshold == 0)
+        {
+            return Err(ConfigError::ZeroFailureThreshold);
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: JwksSource> CachedJWKSBuilder<S> {
+    pub fn build(self) -> Result<CachedJWKS<S>, ConfigError> {
+        self.validate()?;
+        self.build_unchecked()
+    }
+
+    /// Build without validating the configuration, for the deprecated constructors which only ever
+    /// checked the refresh period against the deadline
+    pub(crate) fn build_unchecked(self) -> Result<CachedJWKS<S>, ConfigError> {
+        let jwks_url = self.url.ok_or(ConfigError::MissingUrl)??;
+        let tasks = Arc::new(BackgroundTasks::default());
+
+        Ok(CachedJWKS {
+            runtime: self
+                .runtime
+                .or_else(runtime::current_runtime)
+                .ok_or(ConfigError::NoRuntime)?,
+            clock: self.clock,
+            jwks_url,
+            pkeys: self.format == KeysFormat::PemCertificates,
+            refresh_window: self.refresh_window,
+            refresh_jitter: self.refresh_jitter,
+            max_stale: self.max_stale,
+            ttl_spec: self.ttl_spec,
+            timeout_spec: self.timeout_spec,
+            retry_policy: self
+                .retry_policy
+                .unwrap_or_else(|| self.timeout_spec.into()),
+            retry_schedule: self.retry_schedule,
+            unknown_kid_refresh: self.unknown_kid_refresh,
+            negative_cache: self
+                .negative_cache
+                .and_then(|(ttl, capacity)| Some((ttl, NonZeroUsize::new(capacity)?))),
+            circuit_breaker: self
+                .circuit_breaker
+                .map(|spec| Arc::new(CircuitBreaker::new(spec))),
+            hooks: self.hooks,
+            cache_state: Default::default(),
+            _tasks_owner: Some(Arc::new(TasksOwner(tasks.clone()))),
+            tasks,
+            source: self.source,
+        })
+    }
+}
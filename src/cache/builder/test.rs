@@ -0,0 +1,156 @@
+use super::{CachedJWKSBuilder, ConfigError, KeysFormat};
+use crate::cache::{RefreshWindow, RetryPolicy, RetrySchedule, TimeoutSpec, TtlSpec};
+use crate::circuit_breaker::CircuitBreakerSpec;
+use crate::runtime::Runtime;
+use futures_util::future::BoxFuture;
+use std::time::Duration;
+
+/// Runtime which never runs anything, enough for building the cache
+struct IdleRuntime;
+
+impl Runtime for IdleRuntime {
+    fn spawn(&self, _task: BoxFuture<'static, ()>) {}
+
+    fn sleep(&self, _duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(futures_util::future::pending())
+    }
+}
+
+fn builder() -> CachedJWKSBuilder {
+    CachedJWKSBuilder::new().url("https://example.com/jwks.json")
+}
+
+#[test]
+fn test_build() {
+    let cache = builder()
+        .format(KeysFormat::PemCertificates)
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .negative_cache(Duration::from_secs(60), 0)
+        .build();
+
+    assert!(matches!(cache, Err(ConfigError::ZeroNegativeCacheCapacity)));
+
+    let cache = builder()
+        .negative_cache(Duration::from_secs(60), 10)
+        .build();
+
+    assert!(matches!(
+        cache,
+        Err(ConfigError::NegativeCacheWithoutRefresh)
+    ));
+
+    let cache = builder()
+        .format(KeysFormat::PemCertificates)
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .runtime(IdleRuntime)
+        .build()
+        .unwrap();
+
+    assert_eq!(cache.jwks_url.as_str(), "https://example.com/jwks.json");
+    assert!(cache.pkeys);
+}
+
+#[test]
+fn test_no_runtime() {
+    assert!(matches!(builder().build(), Err(ConfigError::NoRuntime)));
+}
+
+#[test]
+fn test_invalid_url() {
+    assert!(matches!(
+        CachedJWKSBuilder::new().build(),
+        Err(ConfigError::MissingUrl)
+    ));
+    assert!(matches!(
+        CachedJWKSBuilder::new().url("not a url").build(),
+        Err(ConfigError::InvalidUrl(_))
+    ));
+}
+
+#[test]
+fn test_refresh_period_within_deadline() {
+    let result = builder()
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(10)))
+        .timeout_spec(TimeoutSpec {
+            deadline: Duration::from_secs(10),
+            ..Default::default()
+        })
+        .build();
+
+    assert!(matches!(
+        result,
+        Err(ConfigError::RefreshPeriodWithinDeadline { period, deadline })
+            if period == deadline
+    ));
+}
+
+#[test]
+fn test_invalid_fractions() {
+    assert!(matches!(
+        builder()
+            .refresh_window(RefreshWindow::AfterLifetimeFraction(1.5))
+            .build(),
+        Err(ConfigError::InvalidRefreshFraction(_))
+    ));
+    assert!(matches!(
+        builder()
+            .refresh_window(RefreshWindow::AfterLifetimeFraction(f64::NAN))
+            .build(),
+        Err(ConfigError::InvalidRefreshFraction(_))
+    ));
+    assert!(matches!(
+        builder().refresh_jitter(-0.1).build(),
+        Err(ConfigError::InvalidRefreshJitter(_))
+    ));
+}
+
+#[test]
+fn test_invalid_bounds() {
+    assert!(matches!(
+        builder()
+            .ttl_spec(TtlSpec {
+                min_ttl: Duration::from_secs(60),
+                max_ttl: Duration::from_secs(30),
+                ..Default::default()
+            })
+            .build(),
+        Err(ConfigError::InvalidTtlBounds { .. })
+    ));
+    assert!(matches!(
+        builder()
+            .timeout_spec(TimeoutSpec {
+                deadline: Duration::ZERO,
+                ..Default::default()
+            })
+            .build(),
+        Err(ConfigError::ZeroDeadline)
+    ));
+    assert!(matches!(
+        builder()
+            .retry_schedule(RetrySchedule {
+                initial: Duration::from_secs(60),
+                max: Duration::from_secs(1),
+            })
+            .build(),
+        Err(ConfigError::InvalidRetrySchedule { .. })
+    ));
+    assert!(matches!(
+        builder()
+            .retry_policy(RetryPolicy {
+                initial_backoff: Duration::from_secs(10),
+                max_backoff: Duration::from_secs(1),
+                ..Default::default()
+            })
+            .build(),
+        Err(ConfigError::InvalidBackoff { .. })
+    ));
+    assert!(matches!(
+        builder()
+            .circuit_breaker(CircuitBreakerSpec {
+                failure_threshold: 0,
+                ..Default::default()
+            })
+            .build(),
+        Err(ConfigError::ZeroFailureThreshold)
+    ));
+}
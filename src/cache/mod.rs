@@ -1,106 +1,515 @@
-#[cfg(test)]
+mod builder;
+#[cfg(all(test, feature = "tokio"))]
 mod test;
 
-use super::pem_set::PemMap;
+pub use builder::{CachedJWKSBuilder, ConfigError, DefaultHttpClient, KeysFormat};
+
+use super::circuit_breaker::{CircuitBreaker, CircuitState, Permit};
+use super::clock::Clock;
+use super::key_index::KeyIndex;
+use super::negative_cache::NegativeCache;
+use super::pem_set::{CertificateError, PemMap};
+use super::runtime::{self, BackgroundTasks, RefresherTask, Runtime};
+use super::state::{
+    CacheState, CacheStatus, CachedSet, Decision, Election, FetchGuard, FetchOutcome, JWKSCache,
+    JwksSnapshot, Refresh, concluded,
+};
+use arc_swap::Guard;
 use core::future::Future;
-use jsonwebtoken::jwk::JwkSet;
-use spin::RwLock;
+use futures_util::FutureExt;
+use http_cache_semantics::{AfterResponse, CachePolicy};
+use jsonwebtoken::{
+    Algorithm,
+    jwk::{Jwk, JwkSet},
+};
+use std::num::NonZeroUsize;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
-use tokio::sync::Notify;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{oneshot, watch};
 use url::Url;
 
-fn get_expiration(now: SystemTime, req: &reqwest::Request, res: &reqwest::Response) -> SystemTime {
-    now + http_cache_semantics::CachePolicy::new(req, res).time_to_live(now)
+/// Expiration is only reported when response states its freshness explicitly, otherwise it is left to
+/// the cache to apply its default TTL rather than to rely on heuristics
+fn get_expiration(
+    now: SystemTime,
+    policy: &CachePolicy,
+    headers: &http::HeaderMap,
+) -> Option<SystemTime> {
+    has_explicit_freshness(headers).then(|| now + policy.time_to_live(now))
+}
+
+fn has_explicit_freshness(headers: &http::HeaderMap) -> bool {
+    const DIRECTIVES: [&str; 4] = ["max-age", "s-maxage", "no-cache", "no-store"];
+
+    headers.contains_key(http::header::EXPIRES)
+        || headers
+            .get_all(http::header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| {
+                let name = directive.split('=').next().unwrap_or_default().trim();
+
+                DIRECTIVES
+                    .iter()
+                    .any(|known| name.eq_ignore_ascii_case(known))
+            })
+}
+
+/// Read a delta-seconds valued `Cache-Control` directive, e.g. `stale-if-error=300`
+fn cache_control_directive(headers: &http::HeaderMap, name: &str) -> Option<Duration> {
+    headers
+        .get_all(http::header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|directive| {
+            let (key, value) = directive.split_once('=')?;
+
+            if !key.trim().eq_ignore_ascii_case(name) {
+                return None;
+            }
+
+            value
+                .trim()
+                .trim_matches('"')
+                .parse()
+                .ok()
+                .map(Duration::from_secs)
+        })
+}
+
+/// JWK Set as returned by a [`JwksSource`] together with its caching metadata
+#[derive(Debug, Clone)]
+pub struct JwksResponse {
+    /// Fetched JWK Set
+    pub jwks: JwkSet,
+    /// Moment after which the JWK Set is no longer fresh, `None` when the source has no information
+    /// about freshness of the JWK Set
+    pub expires: Option<SystemTime>,
+    /// Value of the `stale-if-error` Cache-Control directive, if the source provided one
+    pub stale_if_error: Option<Duration>,
+    /// Value of the `stale-while-revalidate` Cache-Control directive, if the source provided one
+    pub stale_while_revalidate: Option<Duration>,
+    /// Validators for revalidating the JWK Set once it has to be refreshed, if the source provided
+    /// any
+    pub validators: Option<Validators>,
+}
+
+impl From<(JwkSet, SystemTime)> for JwksResponse {
+    fn from((jwks, expires): (JwkSet, SystemTime)) -> Self {
+        Self {
+            jwks,
+            expires: Some(expires),
+            stale_if_error: None,
+            stale_while_revalidate: None,
+            validators: None,
+        }
+    }
+}
+
+/// Validators of fetched content, which let the source tell whether it has changed since
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    /// `ETag` of the content
+    pub etag: Option<String>,
+    /// `Last-Modified` date of the content
+    pub last_modified: Option<String>,
+    /// Caching policy of the HTTP response the content came with, so caching headers of the
+    /// revalidation response can be combined with it
+    policy: Option<Arc<CachePolicy>>,
+}
+
+impl Validators {
+    pub fn new(etag: Option<String>, last_modified: Option<String>) -> Self {
+        Self {
+            etag,
+            last_modified,
+            policy: None,
+        }
+    }
+
+    /// Validators of the HTTP response, `None` when it has neither `ETag` nor `Last-Modified`
+    fn from_http(headers: &http::HeaderMap, policy: CachePolicy) -> Option<Self> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        };
+        let etag = header(http::header::ETAG);
+        let last_modified = header(http::header::LAST_MODIFIED);
+
+        (etag.is_some() || last_modified.is_some()).then(|| Self {
+            etag,
+            last_modified,
+            policy: Some(Arc::new(policy)),
+        })
+    }
+}
+
+/// Outcome of revalidating previously fetched JWK Set
+#[derive(Debug, Clone)]
+pub enum Revalidated {
+    /// Content has changed, or the source does not support revalidation
+    Modified(JwksResponse),
+    /// Previously fetched JWK Set is still current, only its caching metadata is renewed
+    NotModified {
+        expires: Option<SystemTime>,
+        stale_if_error: Option<Duration>,
+        stale_while_revalidate: Option<Duration>,
+        validators: Option<Validators>,
+    },
+}
+
+/// How a failed attempt is treated when retrying
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Attempt may succeed when retried, after waiting for `retry_after` if the source asked to
+    Transient { retry_after: Option<Duration> },
+    /// Retrying would fail the same way, e.g. because the JWK Set is missing or malformed
+    Permanent,
 }
 
 pub trait JwksSource: Clone + Send + Sync + 'static {
     type Error: core::fmt::Debug + Send + Sync + 'static;
 
-    fn get_jwks_within_deadline(
+    /// Whether an attempt which failed with the error at wall-clock `now` is worth retrying, every
+    /// error is transient unless the source tells otherwise
+    fn classify(_error: &Self::Error, _now: SystemTime) -> ErrorClass {
+        ErrorClass::Transient { retry_after: None }
+    }
+
+    /// Fetch JWK Set, `now` is the wall-clock time of the cache clock which expiration reported in
+    /// the response is relative to
+    fn get_jwks(
         self,
         url: Url,
         as_pkeys: bool,
         now: SystemTime,
-        deadline: Duration,
-    ) -> impl Future<Output = Result<(JwkSet, SystemTime), RequestError<Self::Error>>>
-    + Send
-    + Sync
-    + 'static {
-        async move {
-            let result = tokio::time::timeout(deadline, self.get_jwks(url, as_pkeys, now)).await;
-
-            match result {
-                Ok(res) => res.map_err(RequestError::Client),
-                Err(_) => Err(RequestError::Timeout),
-            }
-        }
-    }
+    ) -> impl Future<Output = Result<JwksResponse, Self::Error>> + Send + Sync + 'static;
 
-    fn get_jwks(
+    /// Fetch JWK Set unless it has not changed since it was returned with the validators. Sources
+    /// without support for conditional requests fetch it again.
+    fn revalidate_jwks(
         self,
         url: Url,
         as_pkeys: bool,
         now: SystemTime,
-    ) -> impl Future<Output = Result<(JwkSet, SystemTime), Self::Error>> + Send + Sync + 'static;
+        _validators: Validators,
+    ) -> impl Future<Output = Result<Revalidated, Self::Error>> + Send + Sync + 'static {
+        let fetch = self.get_jwks(url, as_pkeys, now);
+
+        async move { fetch.await.map(Revalidated::Modified) }
+    }
+}
+
+/// Error of fetching JWK Set over HTTP
+#[derive(Debug, thiserror::Error)]
+pub enum HttpError {
+    #[error("JWKS endpoint responded with status {status}")]
+    Status {
+        status: http::StatusCode,
+        headers: http::HeaderMap,
+    },
+    /// Response body is not a JWK Set, or a map of PEM certificates when those are expected
+    #[error("Could not decode JWKS response: {0}")]
+    Decode(#[from] serde_json::Error),
+    /// None of the PEM certificates could be turned into an RSA key
+    #[error("Could not use PEM certificates of JWKS response: {0}")]
+    Certificate(#[from] CertificateError),
+    /// Connection, TLS or protocol failure, or the body could not be read
+    #[error(transparent)]
+    Client(#[from] reqwest::Error),
+}
+
+fn check_status(res: reqwest::Response) -> Result<reqwest::Response, HttpError> {
+    let status = res.status();
+
+    if status.is_client_error() || status.is_server_error() {
+        Err(HttpError::Status {
+            status,
+            headers: res.headers().clone(),
+        })
+    } else {
+        Ok(res)
+    }
+}
+
+/// Waiting requested by `Retry-After` header, given either in seconds or as an HTTP date
+fn retry_after(headers: &http::HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers
+        .get(http::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date =
+        time::OffsetDateTime::parse(value, &time::format_description::well_known::Rfc2822).ok()?;
+
+    Some(
+        SystemTime::from(date)
+            .duration_since(now)
+            .unwrap_or_default(),
+    )
+}
+
+async fn read_jwks_response(
+    now: SystemTime,
+    req: &reqwest::Request,
+    res: reqwest::Response,
+    as_pkeys: bool,
+) -> Result<JwksResponse, HttpError> {
+    let policy = CachePolicy::new_options(req, &res, now, Default::default());
+    let expires = get_expiration(now, &policy, res.headers());
+    let stale_if_error = cache_control_directive(res.headers(), "stale-if-error");
+    let stale_while_revalidate = cache_control_directive(res.headers(), "stale-while-revalidate");
+    let validators = Validators::from_http(res.headers(), policy);
+    let body = res.bytes().await?;
+    let jwks = if as_pkeys {
+        serde_json::from_slice::<PemMap>(&body)?.into_rsa_jwk_set()?
+    } else {
+        serde_json::from_slice::<JwkSet>(&body)?
+    };
+
+    Ok(JwksResponse {
+        jwks,
+        expires,
+        stale_if_error,
+        stale_while_revalidate,
+        validators,
+    })
 }
 
 impl JwksSource for reqwest::Client {
-    type Error = reqwest::Error;
+    type Error = HttpError;
+
+    /// Server errors, throttling and connection failures are transient, while other error statuses
+    /// and malformed responses are permanent
+    fn classify(error: &HttpError, now: SystemTime) -> ErrorClass {
+        use http::StatusCode;
+
+        match error {
+            HttpError::Status { status, headers }
+                if status.is_server_error()
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::REQUEST_TIMEOUT =>
+            {
+                let throttled = matches!(
+                    *status,
+                    StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+                );
+
+                ErrorClass::Transient {
+                    retry_after: throttled.then(|| retry_after(headers, now)).flatten(),
+                }
+            }
+            HttpError::Status { .. } | HttpError::Decode(_) | HttpError::Certificate(_) => {
+                ErrorClass::Permanent
+            }
+            HttpError::Client(err) if err.is_decode() || err.is_builder() => ErrorClass::Permanent,
+            HttpError::Client(_) => ErrorClass::Transient { retry_after: None },
+        }
+    }
 
     async fn get_jwks(
         self,
         url: Url,
         as_pkeys: bool,
         now: SystemTime,
-    ) -> Result<(JwkSet, SystemTime), Self::Error> {
-        let req = reqwest::Request::new(http::Method::GET, url.clone());
-        let res = reqwest::Client::builder()
-            .build()?
-            .execute(
+    ) -> Result<JwksResponse, Self::Error> {
+        let req = reqwest::Request::new(http::Method::GET, url);
+        let res = check_status(
+            self.execute(
                 // safety: because we control the request creation we can ensure its not a stateful stream and can be copied at all times
                 req.try_clone().expect("Request should be always copyable"),
             )
-            .await?
-            .error_for_status()?;
+            .await?,
+        )?;
 
-        let expiration = get_expiration(now, &req, &res);
-        let jwks = if as_pkeys {
-            res.json::<PemMap>().await?.into_rsa_jwk_set()
-        } else {
-            res.json::<JwkSet>().await?
+        read_jwks_response(now, &req, res, as_pkeys).await
+    }
+
+    async fn revalidate_jwks(
+        self,
+        url: Url,
+        as_pkeys: bool,
+        now: SystemTime,
+        validators: Validators,
+    ) -> Result<Revalidated, Self::Error> {
+        let mut req = reqwest::Request::new(http::Method::GET, url.clone());
+        let conditions = [
+            (http::header::IF_NONE_MATCH, &validators.etag),
+            (http::header::IF_MODIFIED_SINCE, &validators.last_modified),
+        ];
+        for (name, value) in conditions {
+            if let Some(value) = value.as_deref().and_then(|value| value.parse().ok()) {
+                req.headers_mut().insert(name, value);
+            }
+        }
+
+        let res = check_status(
+            self.clone()
+                .execute(req.try_clone().expect("Request should be always copyable"))
+                .await?,
+        )?;
+
+        if res.status() != http::StatusCode::NOT_MODIFIED {
+            return read_jwks_response(now, &req, res, as_pkeys)
+                .await
+                .map(Revalidated::Modified);
+        }
+
+        let (policy, headers) = match validators.policy.as_deref() {
+            Some(policy) => match policy.after_response(&req, &res, now) {
+                AfterResponse::NotModified(policy, parts) => (policy, parts.headers),
+                // validators do not match the cached content, which has to be fetched again
+                AfterResponse::Modified(..) => {
+                    return self
+                        .get_jwks(url, as_pkeys, now)
+                        .await
+                        .map(Revalidated::Modified);
+                }
+            },
+            None => (
+                CachePolicy::new_options(&req, &res, now, Default::default()),
+                res.headers().clone(),
+            ),
         };
 
-        Ok((jwks, expiration))
+        Ok(Revalidated::NotModified {
+            expires: get_expiration(now, &policy, &headers),
+            stale_if_error: cache_control_directive(&headers, "stale-if-error"),
+            stale_while_revalidate: cache_control_directive(&headers, "stale-while-revalidate"),
+            validators: Validators::from_http(&headers, policy).or(Some(validators)),
+        })
     }
 }
 
-/// State machine of the JWKS cache
-#[derive(Debug, Clone, Default)]
-enum JWKSCache {
-    /// There is no data in cache, this is initial state
-    #[default]
-    Empty,
-    /// Cache is empty or expired, fetching of new content is ongoing.
-    /// Contains handle for awaiting for fetching to conclude
-    Fetching(Arc<Notify>),
-    /// Cache is valid, but content is being refreshed in the background
-    Refreshing { expires: SystemTime, jwks: JwkSet },
-    /// Cache is populated, but needs to be revalidated before use
-    Fetched { expires: SystemTime, jwks: JwkSet },
+/// Held only by clones of the cache owned by its users, background tasks work with clones without it
+struct TasksOwner(Arc<BackgroundTasks>);
+
+impl Drop for TasksOwner {
+    fn drop(&mut self) {
+        self.0.abort_all();
+    }
+}
+
+type UpdateHook = Arc<dyn Fn(&JwksSnapshot) + Send + Sync>;
+type ErrorHook = Arc<dyn Fn(&dyn core::fmt::Debug) + Send + Sync>;
+type CircuitHook = Arc<dyn Fn(CircuitState) + Send + Sync>;
+
+/// Callbacks notified about outcomes of fetches and refreshes
+#[derive(Clone, Default)]
+struct Hooks {
+    on_update: Option<UpdateHook>,
+    on_error: Option<ErrorHook>,
+    on_circuit_change: Option<CircuitHook>,
+}
+
+impl Hooks {
+    fn observe<E: core::fmt::Debug>(&self, result: &Result<CachedSet, RequestError<E>>) {
+        match (result, &self.on_update, &self.on_error) {
+            (Ok(cached), Some(on_update), _) => on_update(&cached.snapshot(false)),
+            (Err(err), _, Some(on_error)) => on_error(err),
+            _ => {}
+        }
+    }
+
+    fn circuit_changed(&self, transition: Option<CircuitState>) {
+        let Some(state) = transition else {
+            return;
+        };
+
+        log::warn!("JWKS source circuit breaker is now {state:?}");
+
+        if let Some(on_circuit_change) = &self.on_circuit_change {
+            on_circuit_change(state);
+        }
+    }
 }
 
+/// Failure of a single attempt to get JWK Set from the source
 #[derive(Debug, thiserror::Error)]
-pub enum RequestError<E: core::fmt::Debug> {
+pub enum AttemptError<E: core::fmt::Debug> {
     #[error("Client error: {0}")]
     Client(E),
-    #[error("Timeout for request completion reached")]
+    #[error("JWKS source returned no keys")]
+    EmptyKeySet,
+    #[error("Timeout for a single attempt reached")]
     Timeout,
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum RequestError<E: core::fmt::Debug> {
+    /// Last attempt failed and was not retried, `retried` holds errors of the attempts before it
+    #[error("JWKS source failed: {error}")]
+    Source {
+        error: AttemptError<E>,
+        retried: Vec<AttemptError<E>>,
+    },
+    /// Timeout for request completion reached, `attempts` holds errors of the attempts concluded
+    /// before it
+    #[error("Timeout for request completion reached")]
+    Timeout { attempts: Vec<AttemptError<E>> },
+    /// Circuit breaker denied the attempt, `attempts` holds errors of the attempts made before it
+    #[error("Circuit breaker is open, JWKS source is not called")]
+    CircuitOpen { attempts: Vec<AttemptError<E>> },
+    /// Runtime dropped the fetch before it concluded, such as one which has been shut down
+    #[error("Fetch was dropped by the runtime before it concluded")]
+    Aborted,
+}
+
+impl<E: core::fmt::Debug> RequestError<E> {
+    /// Errors of the failed attempts, in the order they were made
+    pub fn attempts(&self) -> impl Iterator<Item = &AttemptError<E>> {
+        let (attempts, last) = match self {
+            Self::Source { error, retried } => (retried.as_slice(), Some(error)),
+            Self::Timeout { attempts } | Self::CircuitOpen { attempts } => {
+                (attempts.as_slice(), None)
+            }
+            Self::Aborted => (&[][..], None),
+        };
+
+        attempts.iter().chain(last)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LookupError<E: core::fmt::Debug> {
+    #[error("No key with matching key ID in JWK Set")]
+    KeyNotFound,
+    #[error(transparent)]
+    Request(#[from] RequestError<E>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadyError<E: core::fmt::Debug> {
+    #[error("JWK Set was not loaded within {0:?}")]
+    Timeout(Duration),
+    #[error(transparent)]
+    Request(#[from] RequestError<E>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeadlineError<E: core::fmt::Debug> {
+    #[error("Deadline of {0:?} exceeded while JWK Set is being fetched")]
+    DeadlineExceeded(Duration),
+    #[error(transparent)]
+    Request(#[from] RequestError<E>),
+}
+
 impl<T: core::fmt::Debug> From<T> for RequestError<T> {
     fn from(value: T) -> Self {
-        Self::Client(value)
+        Self::Source {
+            error: AttemptError::Client(value),
+            retried: Vec::new(),
+        }
     }
 }
 
@@ -116,6 +525,195 @@ pub struct TimeoutSpec {
     pub deadline: Duration,
 }
 
+/// Schedule for retrying failed refreshes while previously fetched content is still being served
+#[derive(Debug, Clone, Copy)]
+pub struct RetrySchedule {
+    /// Waiting before retrying after the first failure
+    pub initial: Duration,
+    /// Upper bound of the waiting, which doubles with each consecutive failure
+    pub max: Duration,
+}
+
+impl RetrySchedule {
+    fn delay(&self, failures: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(failures.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+impl Default for RetrySchedule {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Retrying of failed attempts within a single fetch. Permanent errors are never retried, while
+/// transient ones are retried after exponentially growing backoff, or after as long as the source
+/// asked with `Retry-After`.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// How many times to retry after the first attempt fails
+    pub retries: u8,
+    /// Backoff before the first retry, which doubles with each following one
+    pub initial_backoff: Duration,
+    /// Upper bound of the backoff
+    pub max_backoff: Duration,
+    /// Wait for a random part of the backoff instead ("full jitter"), so clients failing at the same
+    /// time do not retry all at once
+    pub jitter: bool,
+    /// Upper bound of the waiting asked for by the source
+    pub max_retry_after: Duration,
+}
+
+impl RetryPolicy {
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff);
+
+        if self.jitter {
+            backoff.mul_f64(fastrand::f64())
+        } else {
+            backoff
+        }
+    }
+
+    /// Waiting before the retry of a transient error
+    fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(retry_after) => retry_after.min(self.max_retry_after),
+            None => self.backoff(retry),
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            jitter: true,
+            max_retry_after: Duration::from_secs(30),
+        }
+    }
+}
+
+impl From<TimeoutSpec> for RetryPolicy {
+    /// Retries of the timeout spec with its fixed backoff
+    fn from(timeout_spec: TimeoutSpec) -> Self {
+        Self {
+            retries: timeout_spec.retries,
+            initial_backoff: timeout_spec.backoff,
+            max_backoff: timeout_spec.backoff,
+            jitter: false,
+            max_retry_after: timeout_spec.deadline,
+        }
+    }
+}
+
+/// When to start refreshing JWK Set in the background, ahead of its expiration
+#[derive(Debug, Clone, Copy)]
+pub enum RefreshWindow {
+    /// Fixed period before the expiration
+    BeforeExpiry(Duration),
+    /// Fraction of the JWK Set lifetime after which it gets refreshed, e.g. `0.8` refreshes once 80%
+    /// of the lifetime has passed
+    AfterLifetimeFraction(f64),
+}
+
+impl RefreshWindow {
+    /// How long after being fetched the JWK Set with the lifetime gets refreshed
+    fn refresh_after(&self, lifetime: Duration) -> Duration {
+        match *self {
+            Self::BeforeExpiry(period) => lifetime.saturating_sub(period),
+            Self::AfterLifetimeFraction(fraction) => lifetime.mul_f64(unit_fraction(fraction)),
+        }
+    }
+}
+
+/// Fraction limited to `0.0..=1.0`, invalid fraction is treated as `1.0`
+fn unit_fraction(fraction: f64) -> f64 {
+    if fraction.is_nan() {
+        1.0
+    } else {
+        fraction.clamp(0.0, 1.0)
+    }
+}
+
+/// Handle of the task keeping the cached JWK Set fresh, see [`CachedJWKS::spawn_refresher`].
+/// The task is stopped once the handle is dropped.
+#[derive(Debug)]
+#[must_use = "refresher is stopped once its handle is dropped"]
+pub struct RefresherHandle {
+    /// Missing if the cache was already shut down
+    task: Option<RefresherTask>,
+}
+
+impl RefresherHandle {
+    /// Stop refreshing, refresh which is already ongoing still concludes
+    pub fn stop(self) {
+        // Dropping the handle aborts the refresher
+    }
+
+    /// Whether refresher has stopped, e.g. because it gave up after failures or the cache was
+    /// shut down
+    pub fn is_finished(&self) -> bool {
+        self.task.as_ref().is_none_or(RefresherTask::is_finished)
+    }
+}
+
+impl Drop for RefresherHandle {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// Bounds of the JWK Set lifetime, applied on top of the expiration reported by the source
+#[derive(Debug, Clone, Copy)]
+pub struct TtlSpec {
+    /// Lifetime is never shorter than this, even if the source asks not to cache the JWK Set
+    pub min_ttl: Duration,
+    /// Lifetime is never longer than this, so key rotations are eventually picked up
+    pub max_ttl: Duration,
+    /// Lifetime when the source has no information about freshness of the JWK Set
+    pub default_ttl: Duration,
+}
+
+impl TtlSpec {
+    fn ttl(&self, now: SystemTime, expires: Option<SystemTime>) -> Duration {
+        let ttl = match expires {
+            Some(expires) => expires.duration_since(now).unwrap_or_default(),
+            None => self.default_ttl,
+        };
+
+        ttl.clamp(self.min_ttl, self.max_ttl.max(self.min_ttl))
+    }
+}
+
+impl Default for TtlSpec {
+    fn default() -> Self {
+        Self {
+            min_ttl: Duration::ZERO,
+            // an upper bound still far enough to never overflow `Instant`
+            max_ttl: Duration::from_secs(60 * 60 * 24 * 365),
+            default_ttl: Duration::ZERO,
+        }
+    }
+}
+
 impl Default for TimeoutSpec {
     fn default() -> Self {
         Self {
@@ -129,24 +727,44 @@ impl Default for TimeoutSpec {
 
 #[derive(Clone)]
 pub struct CachedJWKS<S> {
+    runtime: Arc<dyn Runtime>,
+    clock: Arc<dyn Clock>,
     jwks_url: Url,
     pkeys: bool,
-    update_period: Duration,
+    refresh_window: RefreshWindow,
+    refresh_jitter: f64,
+    max_stale: Duration,
+    ttl_spec: TtlSpec,
     timeout_spec: TimeoutSpec,
-    cache_state: Arc<RwLock<JWKSCache>>,
+    retry_policy: RetryPolicy,
+    retry_schedule: RetrySchedule,
+    unknown_kid_refresh: Option<Duration>,
+    negative_cache: Option<(Duration, NonZeroUsize)>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    hooks: Hooks,
+    cache_state: Arc<CacheState>,
+    tasks: Arc<BackgroundTasks>,
+    _tasks_owner: Option<Arc<TasksOwner>>,
     source: S,
 }
 
 impl CachedJWKS<reqwest::Client> {
+    /// Configure the cache, which is fetching JWK Set over HTTP unless given another source
+    pub fn builder() -> CachedJWKSBuilder {
+        CachedJWKSBuilder::new()
+    }
+
+    #[cfg(feature = "tokio")]
+    #[deprecated(note = "use `CachedJWKS::builder`, which reports invalid configuration")]
     pub fn new(
         jwks_url: Url,
         // Period when to refresh in the background before expiration period
         update_period: Duration,
         timeout_spec: TimeoutSpec,
     ) -> Result<Self, reqwest::Error> {
-        Ok(Self::from_source(
+        Ok(Self::from_positional(
             jwks_url,
-            false,
+            KeysFormat::JwkSet,
             update_period,
             timeout_spec,
             reqwest::Client::builder().build()?,
@@ -154,15 +772,17 @@ impl CachedJWKS<reqwest::Client> {
     }
 
     /// Load keys as a map of RSA pub keys
+    #[cfg(feature = "tokio")]
+    #[deprecated(note = "use `CachedJWKS::builder` with `KeysFormat::PemCertificates`")]
     pub fn new_rsa_pkeys(
         pkeys_url: Url,
         // Period when to refresh in the background before expiration period
         update_period: Duration,
         timeout_spec: TimeoutSpec,
     ) -> Result<Self, reqwest::Error> {
-        Ok(Self::from_source(
+        Ok(Self::from_positional(
             pkeys_url,
-            true,
+            KeysFormat::PemCertificates,
             update_period,
             timeout_spec,
             reqwest::Client::builder().build()?,
@@ -171,211 +791,678 @@ impl CachedJWKS<reqwest::Client> {
 }
 
 impl<S: JwksSource> CachedJWKS<S> {
+    #[cfg(feature = "tokio")]
+    #[deprecated(
+        note = "use `CachedJWKS::builder` with `source`, which reports invalid configuration"
+    )]
     pub fn from_source(
         jwks_url: Url,
         pkeys: bool,
         update_period: Duration,
         timeout_spec: TimeoutSpec,
         source: S,
+    ) -> Self {
+        let format = if pkeys {
+            KeysFormat::PemCertificates
+        } else {
+            KeysFormat::JwkSet
+        };
+
+        Self::from_positional(jwks_url, format, update_period, timeout_spec, source)
+    }
+
+    /// Panics when the update period is within the deadline, as the deprecated constructors always
+    /// did. Background work is spawned on the tokio runtime current when the cache is used, as it
+    /// always was, so these can be called outside of a runtime or outlive the one they are called in.
+    #[cfg(feature = "tokio")]
+    fn from_positional(
+        jwks_url: Url,
+        format: KeysFormat,
+        update_period: Duration,
+        timeout_spec: TimeoutSpec,
+        source: S,
     ) -> Self {
         assert!(
             update_period > timeout_spec.deadline,
             "Update period should be greater than timeout deadline"
         );
 
+        let builder = CachedJWKSBuilder::new()
+            .url(jwks_url)
+            .format(format)
+            .refresh_window(RefreshWindow::BeforeExpiry(update_period))
+            .timeout_spec(timeout_spec)
+            .source(source)
+            .runtime(runtime::TokioRuntime::default());
+
+        builder
+            .build_unchecked()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Clone of the cache for background tasks, which does not keep them from being aborted once all
+    /// clones owned by users are dropped
+    fn detached(&self) -> Self {
         Self {
-            jwks_url,
-            pkeys,
-            update_period,
-            timeout_spec,
-            cache_state: Default::default(),
-            source,
+            _tasks_owner: None,
+            ..self.clone()
+        }
+    }
+
+    /// Cache JWK Set fetched at monotonic `now`, while caching headers are interpreted relative to
+    /// wall-clock `system_now`. JWK Set which was not modified since `previous` keeps its parsed keys
+    /// and remembered unknown key IDs, only its lifetime is renewed.
+    fn cache_set(
+        &self,
+        now: Instant,
+        system_now: SystemTime,
+        previous: Option<&CachedSet>,
+        response: Revalidated,
+    ) -> CachedSet {
+        let ((index, jwks, unknown_kids), metadata) = match response {
+            Revalidated::Modified(response) => (
+                (
+                    Arc::new(KeyIndex::new(&response.jwks)),
+                    Arc::new(response.jwks),
+                    self.negative_cache
+                        .map(|(ttl, capacity)| Arc::new(NegativeCache::new(ttl, capacity))),
+                ),
+                (
+                    response.expires,
+                    response.stale_if_error,
+                    response.stale_while_revalidate,
+                    response.validators,
+                ),
+            ),
+            Revalidated::NotModified {
+                expires,
+                stale_if_error,
+                stale_while_revalidate,
+                validators,
+            } => {
+                let previous =
+                    previous.expect("Only previously fetched JWK Set should be revalidated");
+
+                (
+                    (
+                        previous.index.clone(),
+                        previous.jwks.clone(),
+                        previous.unknown_kids.clone(),
+                    ),
+                    (expires, stale_if_error, stale_while_revalidate, validators),
+                )
+            }
+        };
+        let (expires, stale_if_error, stale_while_revalidate, validators) = metadata;
+
+        let ttl = self.ttl_spec.ttl(system_now, expires);
+        let jitter = ttl.mul_f64(self.refresh_jitter * fastrand::f64());
+        let stale_window = self.max_stale.max(stale_if_error.unwrap_or_default());
+        let revalidate_window = stale_while_revalidate.unwrap_or_default();
+        // Source lets expired JWK Set be served while it gets refreshed, so there is no need to
+        // refresh it any earlier
+        let refresh_after = if revalidate_window.is_zero() {
+            self.refresh_window.refresh_after(ttl)
+        } else {
+            ttl
+        };
+
+        CachedSet {
+            jwks,
+            index,
+            unknown_kids,
+            validators,
+            expires: now + ttl,
+            expires_at: system_now + ttl,
+            fetched_at: system_now,
+            refresh_at: now + refresh_after.saturating_sub(jitter),
+            revalidate_until: now + ttl + revalidate_window,
+            stale_until: now + ttl + stale_window.max(revalidate_window),
         }
     }
 
+    /// Fetch JWK Set, or revalidate `previous` when the source gave validators for it
     async fn request(
-        source: S,
-        url: Url,
-        as_pkeys: bool,
+        &self,
         now: SystemTime,
-        timeout: TimeoutSpec,
-    ) -> Result<(JwkSet, SystemTime), RequestError<S::Error>> {
+        previous: Option<&CachedSet>,
+    ) -> Result<Revalidated, RequestError<S::Error>> {
+        let timeout = self.timeout_spec;
+        let validators = previous.and_then(|cached| cached.validators.clone());
+        // Errors of failed attempts and permit of the attempt in flight, kept outside of the deadline
+        // so they outlive it
+        let mut attempts = Vec::new();
+        let mut in_flight = None;
         let perform = async {
             let mut retries = 0u8;
             loop {
-                match source
-                    .clone()
-                    .get_jwks_within_deadline(url.clone(), as_pkeys, now, timeout.retry_after)
-                    .await
-                {
-                    Ok(res) => return Ok(res),
-                    Err(err) => {
-                        if retries == timeout.retries {
-                            return Err(err);
-                        } else {
-                            retries += 1;
-                            tokio::time::sleep(timeout.backoff).await;
-                            continue;
+                let source = self.source.clone();
+                let url = self.jwks_url.clone();
+                let validators = validators.clone();
+                let attempt = async move {
+                    match validators {
+                        Some(validators) => {
+                            source
+                                .revalidate_jwks(url, self.pkeys, now, validators)
+                                .await
+                        }
+                        None => source
+                            .get_jwks(url, self.pkeys, now)
+                            .await
+                            .map(Revalidated::Modified),
+                    }
+                };
+
+                let Some(permit) = self.permit_attempt() else {
+                    return Err(RequestError::CircuitOpen {
+                        attempts: std::mem::take(&mut attempts),
+                    });
+                };
+                in_flight = permit;
+                let (err, class) =
+                    match runtime::timeout(&*self.runtime, timeout.retry_after, attempt).await {
+                        Some(Ok(Revalidated::Modified(response)))
+                            if response.jwks.keys.is_empty() =>
+                        {
+                            (AttemptError::EmptyKeySet, ErrorClass::Permanent)
+                        }
+                        Some(Ok(res)) => {
+                            self.record_attempt(in_flight.take(), true);
+
+                            return Ok(res);
+                        }
+                        Some(Err(err)) => {
+                            let class = S::classify(&err, self.clock.system_time());
+
+                            (AttemptError::Client(err), class)
                         }
+                        None => (
+                            AttemptError::Timeout,
+                            ErrorClass::Transient { retry_after: None },
+                        ),
+                    };
+                // Responding with an error, even a permanent one, still fails the attempt
+                self.record_attempt(in_flight.take(), false);
+
+                match class {
+                    ErrorClass::Transient { retry_after }
+                        if retries < self.retry_policy.retries =>
+                    {
+                        attempts.push(err);
+                        retries += 1;
+                        self.runtime
+                            .sleep(self.retry_policy.delay(retries.into(), retry_after))
+                            .await;
+                    }
+                    _ => {
+                        return Err(RequestError::Source {
+                            error: err,
+                            retried: std::mem::take(&mut attempts),
+                        });
                     }
                 }
             }
         };
 
-        tokio::time::timeout(timeout.deadline, perform)
-            .await
-            .map_err(|_| RequestError::Timeout)?
+        match runtime::timeout(&*self.runtime, timeout.deadline, perform).await {
+            Some(result) => result,
+            None => {
+                // Attempt cut off by the deadline has not reached the source in time either
+                self.record_attempt(in_flight.take(), false);
+
+                Err(RequestError::Timeout { attempts })
+            }
+        }
+    }
+
+    /// Permit an attempt to call the source, `None` when the circuit breaker is open
+    fn permit_attempt(&self) -> Option<Option<Permit<'_>>> {
+        let Some(breaker) = &self.circuit_breaker else {
+            return Some(None);
+        };
+
+        let (permit, transition) = breaker.permit(self.clock.now());
+        self.hooks.circuit_changed(transition);
+
+        permit.map(Some)
+    }
+
+    fn record_attempt(&self, permit: Option<Permit<'_>>, success: bool) {
+        if let Some(permit) = permit {
+            self.hooks
+                .circuit_changed(permit.record(success, self.clock.now()));
+        }
     }
 
     async fn update_notify(
         &self,
-        now: SystemTime,
-    ) -> Result<Option<JwkSet>, RequestError<S::Error>> {
-        let notifier = if let Some(mut cached_state) = self.cache_state.try_write() {
-            let notifier = Arc::new(Notify::new());
+        now: Instant,
+        observed: &Arc<JWKSCache>,
+        previous: Option<CachedSet>,
+    ) -> Option<FetchOutcome<RequestError<S::Error>>> {
+        let election = self.cache_state.elect(observed, previous.clone());
 
-            *cached_state = JWKSCache::Fetching(notifier.clone());
+        self.conclude_election(now, election, previous).await
+    }
 
-            notifier
-        } else {
-            return Ok(None);
+    /// Fetch when elected to, or wait for the fetch in flight. `None` when concluded by someone else.
+    async fn conclude_election(
+        &self,
+        now: Instant,
+        election: Election,
+        previous: Option<CachedSet>,
+    ) -> Option<FetchOutcome<RequestError<S::Error>>> {
+        // Guard resets the state even if the fetch gets dropped without ever being polled
+        let guard = match election {
+            Election::Leader(guard) => guard,
+            Election::Follower(waiters) => {
+                concluded(waiters).await;
+
+                return None;
+            }
+            Election::Outdated => return None,
         };
 
-        let result = Self::request(
-            self.source.clone(),
-            self.jwks_url.clone(),
-            self.pkeys,
-            now,
-            self.timeout_spec,
-        )
-        .await;
-
-        let result = {
-            let mut cached_state = self.cache_state.write();
-
-            match result {
-                Ok((jwks, expires)) => {
-                    *cached_state = JWKSCache::Fetched {
-                        expires,
-                        jwks: jwks.clone(),
-                    };
+        let (result_tx, result_rx) = oneshot::channel();
+        let fetch = AssertUnwindSafe(self.detached().fetch(now, previous, guard)).catch_unwind();
+        let fetch = async move {
+            let _ = result_tx.send(fetch.await);
+        };
 
-                    Ok(Some(jwks))
-                }
-                // Could not fetch in time, let follow up request try again later
-                Err(err) => {
-                    *cached_state = JWKSCache::Empty;
+        // Fetch in a separate task, so it concludes and releases the waiters even if the caller
+        // gets cancelled while awaiting it
+        if let Err(fetch) = self.tasks.spawn(&*self.runtime, fetch) {
+            // Cache was shut down, fetch guard still concludes the state if the caller gets cancelled
+            fetch.await;
+        }
 
-                    Err(err)
-                }
+        match result_rx.await {
+            Ok(Ok(outcome)) => Some(outcome),
+            Ok(Err(panic)) => std::panic::resume_unwind(panic),
+            // Fetch was aborted by shutting down, fetch guard has reset the state and the caller
+            // fetches by itself from now on
+            Err(_) if self.tasks.is_shut_down() => None,
+            // Runtime dropped the fetch, electing another one would only get it dropped again
+            Err(_) => Some(FetchOutcome::Failed(RequestError::Aborted)),
+        }
+    }
+
+    async fn fetch(
+        self,
+        now: Instant,
+        previous: Option<CachedSet>,
+        _guard: FetchGuard,
+    ) -> FetchOutcome<RequestError<S::Error>> {
+        let system_now = self.clock.system_time();
+        let result = self
+            .request(system_now, previous.as_ref())
+            .await
+            .map(|response| self.cache_set(now, system_now, previous.as_ref(), response));
+        self.observe(&result);
+
+        let (new_state, outcome) =
+            JWKSCache::after_fetch(now, previous, result, self.retry_schedule.delay(1));
+
+        self.cache_state.store(new_state);
+
+        outcome
+    }
+
+    fn observe(&self, result: &Result<CachedSet, RequestError<S::Error>>) {
+        self.cache_state
+            .record_outcome(result.as_ref().err().map(|err| format!("{err:?}")));
+        self.hooks.observe(result);
+    }
+
+    /// Trigger refresh of JWKS in the background when cached JWKS can still be served, but is about to expire
+    /// or previous refresh failed, if process dies or cache gets dropped then we do not care if this completes
+    fn update_in_background(&self, now: Instant, observed: &Arc<JWKSCache>, refresh: Refresh) {
+        // Cache does not refresh anymore
+        if self.tasks.is_shut_down() {
+            return;
+        }
+
+        let cached = refresh.cached.clone();
+        if let Some(done) = self.cache_state.begin_refresh(observed, refresh) {
+            // Shutting down in the meantime drops the refresh, which releases its waiters
+            let _ = self
+                .tasks
+                .spawn(&*self.runtime, self.detached().refresh(now, cached, done));
+        }
+    }
+
+    /// Fetch JWK Set in a background task, so it gets loaded without anyone waiting for it
+    fn fetch_in_background(&self) {
+        if self.tasks.is_shut_down() {
+            return;
+        }
+
+        let cache = self.detached();
+        let _ = self.tasks.spawn(&*self.runtime, async move {
+            let _ = cache.get_snapshot().await;
+        });
+    }
+
+    /// Refresh in the background, waiters are released once `done` gets dropped
+    async fn refresh(self, now: Instant, cached: CachedSet, done: watch::Sender<bool>) {
+        let system_now = self.clock.system_time();
+        let result = self
+            .request(system_now, Some(&cached))
+            .await
+            .map(|response| self.cache_set(now, system_now, Some(&cached), response));
+
+        if let Err(err) = &result {
+            log::error!("Error while refreshing JWKS in the background: {err:?}");
+        }
+        self.observe(&result);
+
+        self.cache_state
+            .conclude_refresh(self.clock.now(), &done, result.ok(), |failures| {
+                self.retry_schedule.delay(failures)
+            });
+    }
+
+    pub async fn get(&self) -> Result<Arc<JwkSet>, RequestError<S::Error>> {
+        self.get_snapshot().await.map(JwksSnapshot::into_jwks)
+    }
+
+    /// Same as [`CachedJWKS::get_snapshot`], but waits at most `deadline` for JWK Set to be fetched.
+    /// Fetch itself is not cancelled once the deadline is exceeded, previous JWK Set is served stale
+    /// in the meantime if it is allowed to be.
+    pub async fn get_with_deadline(
+        &self,
+        deadline: Duration,
+    ) -> Result<JwksSnapshot, DeadlineError<S::Error>> {
+        match runtime::timeout(&*self.runtime, deadline, self.get_snapshot()).await {
+            Some(result) => result.map_err(DeadlineError::Request),
+            None => self.peek().ok_or(DeadlineError::DeadlineExceeded(deadline)),
+        }
+    }
+
+    /// Fetch new content ahead of its expiration, or join the fetch or refresh if one is already
+    /// ongoing. Also tells whether the source answered with the content, instead of it being served
+    /// stale because fetching failed.
+    /// Start fetching new content, or join the refresh in flight which is already fetching it
+    fn begin_forced_refresh(&self) -> (Election, Option<CachedSet>) {
+        let observed = self.cache_state.load_full();
+
+        match &*observed {
+            JWKSCache::Refreshing { done, .. } => (Election::Follower(done.clone()), None),
+            _ => {
+                let previous = observed.cached().cloned();
+
+                (
+                    self.cache_state.elect(&observed, previous.clone()),
+                    previous,
+                )
             }
-        };
+        }
+    }
 
-        notifier.notify_waiters();
+    /// Conclude the forced refresh, also telling whether the source has answered it
+    async fn force_refresh(
+        &self,
+        now: Instant,
+        (election, previous): (Election, Option<CachedSet>),
+    ) -> Result<(JwksSnapshot, bool), RequestError<S::Error>> {
+        match self.conclude_election(now, election, previous).await {
+            Some(outcome) => outcome.fetched().map(|snapshot| (snapshot, true)),
+            // Concluded by someone else, whose fetch failed if previous content is now served stale
+            None => {
+                let answered = !matches!(**self.cache_state.load(), JWKSCache::Failing { .. });
 
-        result
+                Ok((self.get_snapshot().await?, answered))
+            }
+        }
     }
 
-    /// Trigger refresh of JWKS in the background when cached JWKS is stil valid but about to expire,
-    /// if process dies then we do not care if this completes
-    fn update_in_background(&self, now: SystemTime, old_jwks: JwkSet, old_expires: SystemTime) {
-        {
-            let mut cache_state = self.cache_state.write();
+    async fn lookup(
+        &self,
+        kid: &str,
+        find: impl Fn(&JwksSnapshot) -> Option<&Arc<Jwk>>,
+    ) -> Result<Arc<Jwk>, LookupError<S::Error>> {
+        let snapshot = self.get_snapshot().await?;
 
-            *cache_state = JWKSCache::Refreshing {
-                expires: old_expires,
-                jwks: old_jwks,
-            };
+        if let Some(jwk) = find(&snapshot) {
+            return Ok(jwk.clone());
         }
 
-        let cache_state = self.cache_state.clone();
-        let jwks_url = self.jwks_url.clone();
-        let timeout_spec = self.timeout_spec;
-        let source = self.source.clone();
-        let as_pkeys = self.pkeys;
+        let now = self.clock.now();
+        if let Some(unknown_kids) = snapshot.unknown_kids() {
+            if unknown_kids.contains(kid, now) {
+                return Err(LookupError::KeyNotFound);
+            }
+        }
+
+        let Some(min_interval) = self.unknown_kid_refresh else {
+            return Err(LookupError::KeyNotFound);
+        };
 
-        tokio::spawn(async move {
-            let result = Self::request(source, jwks_url, as_pkeys, now, timeout_spec).await;
+        let Some(forced) = self
+            .cache_state
+            .claim_forced_refresh(now, min_interval, || self.begin_forced_refresh())
+        else {
+            // Refresh forced by someone else may still be bringing the key
+            let in_flight = match &**self.cache_state.load() {
+                JWKSCache::Fetching { done, .. } | JWKSCache::Refreshing { done, .. } => {
+                    Some(done.clone())
+                }
+                _ => None,
+            };
 
-            if let Err(err) = &result {
-                log::error!("Error while refreshing JWKS in the background: {err:?}");
+            if let Some(done) = in_flight {
+                concluded(done).await;
+
+                let snapshot = self.get_snapshot().await?;
+                if let Some(jwk) = find(&snapshot) {
+                    return Ok(jwk.clone());
+                }
             }
 
-            let mut cache_state = cache_state.write();
-
-            let new_state = match cache_state.to_owned() {
-                JWKSCache::Empty => match result {
-                    Ok((jwks, expires)) => JWKSCache::Fetched { expires, jwks },
-                    Err(_) => JWKSCache::Empty,
-                },
-                JWKSCache::Fetching(notify) => {
-                    if let Ok((jwks, expires)) = result {
-                        notify.notify_waiters();
-                        JWKSCache::Fetched { expires, jwks }
-                    } else {
-                        JWKSCache::Fetching(notify)
+            return Err(LookupError::KeyNotFound);
+        };
+
+        let (snapshot, answered) = self.force_refresh(now, forced).await?;
+
+        if let Some(jwk) = find(&snapshot) {
+            return Ok(jwk.clone());
+        }
+
+        // Key ID is only known to be missing once the source has answered without it
+        if let Some(unknown_kids) = snapshot.unknown_kids().filter(|_| answered) {
+            unknown_kids.insert(kid, now);
+        }
+
+        Err(LookupError::KeyNotFound)
+    }
+
+    /// Key with matching key ID from the cached JWK Set
+    pub async fn get_key(&self, kid: &str) -> Result<Arc<Jwk>, LookupError<S::Error>> {
+        self.lookup(kid, |snapshot| snapshot.get_key(kid)).await
+    }
+
+    /// Key with matching key ID from the cached JWK Set, which can be used for verifying signatures
+    /// made with the algorithm
+    pub async fn find(&self, kid: &str, alg: Algorithm) -> Result<Arc<Jwk>, LookupError<S::Error>> {
+        self.lookup(kid, |snapshot| snapshot.find(kid, alg)).await
+    }
+
+    /// Same as [`CachedJWKS::get`], but also reports whether served JWK Set is stale
+    pub async fn get_snapshot(&self) -> Result<JwksSnapshot, RequestError<S::Error>> {
+        let now = self.clock.now();
+        loop {
+            let cached_state = self.cache_state.load();
+
+            match cached_state.decide(now) {
+                Decision::Serve { snapshot, refresh } => {
+                    if let Some(refresh) = refresh {
+                        self.update_in_background(now, &cached_state, refresh);
                     }
+
+                    return Ok(snapshot);
                 }
-                JWKSCache::Refreshing { expires, jwks } => {
-                    if let Ok((jwks, expires)) = result {
-                        JWKSCache::Fetched { expires, jwks }
-                    } else {
-                        JWKSCache::Refreshing { expires, jwks }
+                Decision::Fetch(previous) => {
+                    let observed = Guard::into_inner(cached_state);
+
+                    if let Some(outcome) = self.update_notify(now, &observed, previous).await {
+                        return outcome.or_stale();
                     }
+
+                    // fetch was concluded by someone else or state changed since reading it, reload
                 }
-                JWKSCache::Fetched { expires, jwks } => {
-                    if let Ok((jwks, expires)) = result {
-                        JWKSCache::Fetched { expires, jwks }
-                    } else {
-                        JWKSCache::Refreshing { expires, jwks }
-                    }
+                Decision::Wait(waiters) => {
+                    drop(cached_state);
+
+                    concluded(waiters).await;
+
+                    // fetching has concluded, reload
                 }
-            };
+            }
+        }
+    }
 
-            *cache_state = new_state;
-        });
+    /// Cached JWK Set, without ever waiting for it to be fetched, `None` when there is none that
+    /// can be served. Refreshing or fetching it is started in the background when due.
+    pub fn try_get(&self) -> Option<JwksSnapshot> {
+        let now = self.clock.now();
+        let cached_state = self.cache_state.load();
+
+        match cached_state.decide(now) {
+            Decision::Serve { snapshot, refresh } => {
+                if let Some(refresh) = refresh {
+                    self.update_in_background(now, &cached_state, refresh);
+                }
+
+                Some(snapshot)
+            }
+            Decision::Fetch(_) => {
+                self.fetch_in_background();
+
+                cached_state.peek(now)
+            }
+            Decision::Wait(_) => cached_state.peek(now),
+        }
     }
 
-    pub async fn get(&self) -> Result<JwkSet, RequestError<S::Error>> {
-        let now = SystemTime::now();
+    /// Same as [`CachedJWKS::try_get`], but never starts fetching or refreshing
+    pub fn peek(&self) -> Option<JwksSnapshot> {
+        self.cache_state.load().peek(self.clock.now())
+    }
+
+    /// Keep cached JWK Set fresh independently of traffic, by refreshing it in the background once its
+    /// refresh window is reached, or fetching it right away while cache is empty. Refreshes are
+    /// performed at most once per initial delay of the retry schedule.
+    ///
+    /// Failed refreshes are retried according to the retry schedule, refresher gives up after
+    /// `max_failures` consecutive failures, or keeps going when it is `None`.
+    pub fn spawn_refresher(&self, max_failures: Option<u32>) -> RefresherHandle {
+        RefresherHandle {
+            task: self
+                .tasks
+                .spawn_refresher(&*self.runtime, self.detached().keep_fresh(max_failures)),
+        }
+    }
+
+    /// Load JWK Set ahead of the first read, or join its ongoing fetch
+    pub async fn prefetch(&self) -> Result<JwksSnapshot, RequestError<S::Error>> {
+        self.get_snapshot().await
+    }
+
+    /// Same as [`CachedJWKS::prefetch`], but gives up waiting after `timeout`. Fetch itself is not
+    /// cancelled, so the cache still gets loaded once it concludes.
+    pub async fn wait_ready(
+        &self,
+        timeout: Duration,
+    ) -> Result<JwksSnapshot, ReadyError<S::Error>> {
+        runtime::timeout(&*self.runtime, timeout, self.prefetch())
+            .await
+            .ok_or(ReadyError::Timeout(timeout))?
+            .map_err(ReadyError::Request)
+    }
+
+    /// [`CachedJWKS::wait_ready`] for all the caches concurrently, results are in the same order
+    pub async fn wait_all_ready<'a>(
+        caches: impl IntoIterator<Item = &'a Self>,
+        timeout: Duration,
+    ) -> Vec<Result<JwksSnapshot, ReadyError<S::Error>>>
+    where
+        S: 'a,
+    {
+        futures_util::future::join_all(caches.into_iter().map(|cache| cache.wait_ready(timeout)))
+            .await
+    }
+
+    /// Health of the cache, for readiness probes and diagnostics. Never waits for the cache.
+    pub fn status(&self) -> CacheStatus {
+        self.cache_state.status(self.clock.now())
+    }
+
+    /// State of the circuit breaker in front of the source, `None` when there is none
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.circuit_breaker.as_deref().map(CircuitBreaker::state)
+    }
+
+    /// Stop all background work of the cache, waiting up to `deadline` for ongoing fetches and
+    /// refreshes to conclude before cancelling them. Refreshers are stopped right away and no new
+    /// background work is started afterwards, though JWK Set is still fetched when requested.
+    ///
+    /// Returns whether ongoing work concluded in time.
+    pub async fn shutdown(&self, deadline: Duration) -> bool {
+        self.tasks.shut_down(&*self.runtime, deadline).await
+    }
+
+    async fn keep_fresh(self, max_failures: Option<u32>) {
+        let mut failures = 0;
+        let mut next_attempt = None;
+
         loop {
-            let cached_state = self.cache_state.read().clone();
-
-            match cached_state {
-                JWKSCache::Empty => {
-                    if let Some(jwks) = self.update_notify(now).await? {
-                        return Ok(jwks);
-                    } else {
-                        // state changed since reading it, reload
-                        continue;
-                    }
-                }
-                JWKSCache::Fetching(notifier) => {
-                    notifier.notified().await;
+            let now = self.clock.now();
 
-                    // we got notified about change in state, reload
+            let due = match &**self.cache_state.load() {
+                JWKSCache::Empty => now,
+                JWKSCache::Fetching { done, .. } | JWKSCache::Refreshing { done, .. } => {
+                    concluded(done.clone()).await;
                     continue;
                 }
-                JWKSCache::Refreshing { expires: _, jwks } => {
-                    // Refresh mechanism should guarantee it will change the state before cache is no longer valid
-                    return Ok(jwks);
-                }
-                JWKSCache::Fetched { expires, jwks } => {
-                    if now >= expires {
-                        if let Some(jwks) = self.update_notify(now).await? {
-                            return Ok(jwks);
-                        } else {
-                            // state changed since reading it, reload
-                            continue;
-                        }
-                    }
+                JWKSCache::Fetched(cached) => cached.refresh_at,
+                JWKSCache::Failing {
+                    cached, retry_at, ..
+                } => (*retry_at).min(cached.stale_until),
+            };
+            let due = next_attempt.map_or(due, |next_attempt| due.max(next_attempt));
 
-                    if now + self.update_period >= expires {
-                        self.update_in_background(now, jwks.clone(), expires);
+            if due > now {
+                self.runtime.sleep(due - now).await;
+                continue;
+            }
+
+            // Let the state machine refresh or fetch as due, then wait for it to conclude
+            if let Err(err) = self.get_snapshot().await {
+                log::warn!("Refresher could not fetch JWKS: {err:?}");
+            }
+
+            let concluded_state = loop {
+                let state = self.cache_state.load_full();
+                match &*state {
+                    JWKSCache::Fetching { done, .. } | JWKSCache::Refreshing { done, .. } => {
+                        concluded(done.clone()).await
                     }
+                    _ => break state,
+                }
+            };
 
-                    return Ok(jwks);
+            if let JWKSCache::Fetched(_) = *concluded_state {
+                failures = 0;
+                next_attempt = Some(self.clock.now() + self.retry_schedule.initial);
+            } else {
+                failures += 1;
+
+                if max_failures.is_some_and(|max_failures| failures >= max_failures) {
+                    log::error!("Refresher gave up after {failures} consecutive failures");
+                    return;
                 }
+
+                next_attempt = Some(self.clock.now() + self.retry_schedule.delay(failures));
             }
         }
     }
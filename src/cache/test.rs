@@ -1,20 +1,26 @@
-use super::{CachedJWKS, JwksSource, RequestError, TimeoutSpec};
+use super::{
+    AttemptError, CachedJWKS, DeadlineError, ErrorClass, HttpError, JwksResponse, JwksSource,
+    KeysFormat, LookupError, ReadyError, RefreshWindow, RequestError, RetryPolicy, RetrySchedule,
+    Revalidated, TimeoutSpec, TtlSpec, Validators,
+};
+use crate::circuit_breaker::{CircuitBreakerSpec, CircuitState};
+use crate::clock::{Clock, ManualClock};
+use crate::pem_set::CertificateError;
+use crate::runtime::Runtime;
+use crate::state::CachePhase;
+use futures_util::future::BoxFuture;
 use jsonwebtoken::jwk::JwkSet;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 const JWKS_SAMPLE: &str = include_str!("../../jwks-sample.json");
 
 #[tokio::test]
 async fn test_reqwest_gcp_jwk_integration() {
-    let cache = CachedJWKS::new(
-        "https://www.googleapis.com/service_accounts/v1/jwk/securetoken@system.gserviceaccount.com"
-            .parse()
-            .unwrap(),
-        Duration::from_secs(60 * 60),
-        TimeoutSpec::default(),
-    )
-    .unwrap();
+    let cache = CachedJWKS::builder()
+        .url("https://www.googleapis.com/service_accounts/v1/jwk/securetoken@system.gserviceaccount.com")
+        .build()
+        .unwrap();
 
     let jwks = cache.get().await.unwrap();
 
@@ -23,69 +29,534 @@ async fn test_reqwest_gcp_jwk_integration() {
 
 #[tokio::test]
 async fn test_reqwest_gcp_pub_keys_integration() {
-    let cache = CachedJWKS::new_rsa_pkeys(
-        "https://www.googleapis.com/identitytoolkit/v3/relyingparty/publicKeys"
-            .parse()
-            .unwrap(),
-        Duration::from_secs(60 * 60),
-        TimeoutSpec::default(),
-    )
-    .unwrap();
+    let cache = CachedJWKS::builder()
+        .url("https://www.googleapis.com/identitytoolkit/v3/relyingparty/publicKeys")
+        .format(KeysFormat::PemCertificates)
+        .build()
+        .unwrap();
 
     let jwks = cache.get().await.unwrap();
 
     assert_eq!(jwks.keys.len(), 5);
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MockError {
+    Unavailable,
+    Throttled,
+    NotFound,
+}
+
 #[derive(Clone)]
 struct JwksSourceMock {
-    jwks: JwkSet,
-    expires: Duration,
+    jwks: Arc<Mutex<JwkSet>>,
+    expires: Option<Duration>,
     take_time: Duration,
+    stale_if_error: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+    failing: Arc<Mutex<Option<MockError>>>,
     fetched: Arc<Mutex<usize>>,
+    etag: Arc<Mutex<Option<String>>>,
+    not_modified: Arc<Mutex<usize>>,
 }
 
 impl JwksSourceMock {
     pub fn new(expires: Duration, take_time: Duration) -> Self {
         Self {
-            jwks: serde_json::from_str(JWKS_SAMPLE).unwrap(),
-            expires,
+            jwks: Arc::new(Mutex::new(serde_json::from_str(JWKS_SAMPLE).unwrap())),
+            expires: Some(expires),
             take_time,
+            stale_if_error: None,
+            stale_while_revalidate: None,
+            failing: Arc::new(Mutex::new(None)),
             fetched: Arc::new(Mutex::new(0)),
+            etag: Arc::new(Mutex::new(None)),
+            not_modified: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Serve JWK Set with `ETag`, which changes with rotated keys
+    pub fn with_etag(self, etag: &str) -> Self {
+        *self.etag.lock().unwrap() = Some(etag.to_owned());
+        self
+    }
+
+    pub fn rotate_key(&self, kid: &str) {
+        let mut jwks = self.jwks.lock().unwrap();
+        jwks.keys[0].common.key_id = Some(kid.to_owned());
+
+        let mut etag = self.etag.lock().unwrap();
+        if etag.is_some() {
+            *etag = Some(kid.to_owned());
         }
     }
+
+    pub fn set_failing(&self, failing: bool) {
+        self.fail_with(failing.then_some(MockError::Unavailable));
+    }
+
+    pub fn fail_with(&self, error: Option<MockError>) {
+        *self.failing.lock().unwrap() = error;
+    }
 }
 
 impl JwksSource for JwksSourceMock {
-    type Error = ();
+    type Error = MockError;
+
+    fn classify(error: &MockError, _now: SystemTime) -> ErrorClass {
+        match error {
+            MockError::Unavailable => ErrorClass::Transient { retry_after: None },
+            MockError::Throttled => ErrorClass::Transient {
+                retry_after: Some(Duration::from_millis(20)),
+            },
+            MockError::NotFound => ErrorClass::Permanent,
+        }
+    }
 
     async fn get_jwks(
         self,
         _url: url::Url,
         _as_pkeys: bool,
         now: SystemTime,
-    ) -> Result<(JwkSet, SystemTime), Self::Error> {
+    ) -> Result<JwksResponse, Self::Error> {
         {
             let mut counter = self.fetched.lock().unwrap();
             *counter += 1;
         }
 
-        tokio::time::sleep(self.take_time).await;
+        if !self.take_time.is_zero() {
+            tokio::time::sleep(self.take_time).await;
+        }
+
+        if let Some(err) = *self.failing.lock().unwrap() {
+            return Err(err);
+        }
+
+        Ok(JwksResponse {
+            jwks: self.jwks.lock().unwrap().clone(),
+            expires: self.expires.map(|expires| now + expires),
+            stale_if_error: self.stale_if_error,
+            stale_while_revalidate: self.stale_while_revalidate,
+            validators: self
+                .etag
+                .lock()
+                .unwrap()
+                .clone()
+                .map(|etag| Validators::new(Some(etag), None)),
+        })
+    }
+
+    async fn revalidate_jwks(
+        self,
+        url: url::Url,
+        as_pkeys: bool,
+        now: SystemTime,
+        validators: Validators,
+    ) -> Result<Revalidated, Self::Error> {
+        if self.failing.lock().unwrap().is_some() || *self.etag.lock().unwrap() != validators.etag {
+            return self
+                .get_jwks(url, as_pkeys, now)
+                .await
+                .map(Revalidated::Modified);
+        }
+
+        *self.not_modified.lock().unwrap() += 1;
+
+        Ok(Revalidated::NotModified {
+            expires: self.expires.map(|expires| now + expires),
+            stale_if_error: self.stale_if_error,
+            stale_while_revalidate: self.stale_while_revalidate,
+            validators: None,
+        })
+    }
+}
+
+/// Serve the responses in order, one per connection, and hand back heads of the received requests
+async fn serve_http(responses: Vec<String>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/jwks.json", listener.local_addr().unwrap());
+
+    let server = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut buf = [0; 1024];
+                let read = stream.read(&mut buf).await.unwrap();
+                head.extend_from_slice(&buf[..read]);
+            }
+
+            requests.push(String::from_utf8(head).unwrap().to_lowercase());
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+
+        requests
+    });
+
+    (url, server)
+}
+
+/// Move the cache clock forward along with the paused runtime timers, a millisecond at a time so
+/// that background work wakes up at the same time on both
+async fn advance(clock: &ManualClock, duration: Duration) {
+    let step = Duration::from_millis(1);
+    let mut elapsed = Duration::ZERO;
+
+    while elapsed < duration {
+        clock.advance(step);
+        tokio::time::sleep(step).await;
+        elapsed += step;
+    }
+}
+
+#[tokio::test]
+async fn test_reqwest_revalidation() {
+    let (url, server) = serve_http(vec![
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nCache-Control: max-age=60\r\n\
+             ETag: \"v1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{JWKS_SAMPLE}",
+            JWKS_SAMPLE.len()
+        ),
+        "HTTP/1.1 304 Not Modified\r\nCache-Control: max-age=120\r\nETag: \"v1\"\r\n\
+         Connection: close\r\n\r\n"
+            .to_owned(),
+    ])
+    .await;
+    let url: url::Url = url.parse().unwrap();
+    let client = reqwest::Client::new();
+    let now = SystemTime::now();
+
+    let response = client
+        .clone()
+        .get_jwks(url.clone(), false, now)
+        .await
+        .unwrap();
+    let validators = response.validators.unwrap();
+
+    assert_eq!(response.expires, Some(now + Duration::from_secs(60)));
+    assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+
+    let revalidated = client
+        .revalidate_jwks(url, false, now, validators)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        revalidated,
+        Revalidated::NotModified { expires: Some(expires), validators: Some(_), .. }
+            if expires == now + Duration::from_secs(120)
+    ));
+
+    let requests = server.await.unwrap();
+    assert!(!requests[0].contains("if-none-match"));
+    assert!(requests[1].contains("if-none-match: \"v1\""));
+}
+
+#[tokio::test]
+async fn test_reqwest_errors() {
+    let garbage_pem_map =
+        r#"{"garbage": "-----BEGIN CERTIFICATE-----\nZ2FyYmFnZQ==\n-----END CERTIFICATE-----\n"}"#;
+    let (url, server) = serve_http(vec![
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned(),
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 6\r\n\
+         Connection: close\r\n\r\n<html>"
+            .to_owned(),
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 15\r\n\
+         Connection: close\r\n\r\n{\"kid\": \"nope\"}"
+            .to_owned(),
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{garbage_pem_map}",
+            garbage_pem_map.len()
+        ),
+    ])
+    .await;
+    let url: url::Url = url.parse().unwrap();
+    let client = reqwest::Client::new();
+    let now = SystemTime::now();
+
+    assert!(matches!(
+        client.clone().get_jwks(url.clone(), false, now).await,
+        Err(HttpError::Status { status, .. }) if status == http::StatusCode::NOT_FOUND
+    ));
+    assert!(matches!(
+        client.clone().get_jwks(url.clone(), false, now).await,
+        Err(HttpError::Decode(_))
+    ));
+    assert!(matches!(
+        client.clone().get_jwks(url.clone(), true, now).await,
+        Err(HttpError::Decode(_))
+    ));
+    assert!(matches!(
+        client.get_jwks(url, true, now).await,
+        Err(HttpError::Certificate(CertificateError::Parse { kid, .. })) if kid == "garbage"
+    ));
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_status() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_secs(20), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(15)))
+        .retry_policy(RetryPolicy {
+            retries: 0,
+            ..Default::default()
+        })
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    let status = cache.status();
+    assert_eq!(status.phase, CachePhase::Empty);
+    assert_eq!(status.fetched_at, None);
+    assert_eq!(status.keys, 0);
+
+    cache.get().await.unwrap();
+    let status = cache.status();
+    assert_eq!(status.phase, CachePhase::Fresh);
+    assert_eq!(status.fetched_at, Some(clock.system_time()));
+    assert_eq!(
+        status.expires,
+        Some(clock.system_time() + Duration::from_secs(20))
+    );
+    assert_eq!(status.keys, 1);
+    assert!(!status.refreshing);
+
+    clock.advance(Duration::from_secs(20));
+    assert_eq!(cache.status().phase, CachePhase::Expired);
+
+    source.set_failing(true);
+    let _ = cache.get().await;
+    let status = cache.status();
+    assert_eq!(status.consecutive_failures, 1);
+    assert!(status.last_error.unwrap().contains("Unavailable"));
+
+    source.set_failing(false);
+    cache.get().await.unwrap();
+    let status = cache.status();
+    assert_eq!(status.consecutive_failures, 0);
+    assert!(
+        status.last_error.is_some(),
+        "Last error should be kept after recovering"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_wait_ready() {
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::from_millis(50));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    assert!(matches!(
+        cache.wait_ready(Duration::from_millis(10)).await,
+        Err(ReadyError::Timeout(_))
+    ));
+    assert_eq!(cache.status().phase, CachePhase::Fetching);
+
+    cache.wait_ready(Duration::from_secs(1)).await.unwrap();
+    assert_eq!(
+        *source.fetched.lock().unwrap(),
+        1,
+        "Giving up waiting should not cancel the fetch"
+    );
+
+    let failing = JwksSourceMock::new(Duration::from_secs(60), Duration::ZERO);
+    failing.fail_with(Some(MockError::NotFound));
+    let other = CachedJWKS::builder()
+        .url("https://example.com")
+        .source(failing)
+        .build()
+        .unwrap();
+
+    let results = CachedJWKS::wait_all_ready([&cache, &other], Duration::from_secs(1)).await;
+    assert!(results[0].is_ok());
+    assert!(matches!(
+        results[1],
+        Err(ReadyError::Request(RequestError::Source { .. }))
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_try_get() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_secs(20), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(15)))
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    assert!(cache.peek().is_none());
+    assert!(cache.try_get().is_none());
+    assert!(cache.try_get().is_none(), "Fetch should not be waited for");
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(*source.fetched.lock().unwrap(), 1);
+    assert!(!cache.peek().unwrap().is_stale());
+
+    clock.advance(Duration::from_secs(10));
+    assert!(cache.peek().is_some());
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(
+        *source.fetched.lock().unwrap(),
+        1,
+        "Peeking should not refresh"
+    );
+
+    assert!(cache.try_get().is_some());
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(*source.fetched.lock().unwrap(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_get_with_deadline() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_secs(20), Duration::from_millis(50));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .max_stale(Duration::from_secs(60))
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    assert!(matches!(
+        cache.get_with_deadline(Duration::from_millis(10)).await,
+        Err(DeadlineError::DeadlineExceeded(_))
+    ));
+    let snapshot = cache
+        .get_with_deadline(Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert!(!snapshot.is_stale());
+    assert_eq!(
+        *source.fetched.lock().unwrap(),
+        1,
+        "Exceeding the deadline should not cancel the fetch"
+    );
+
+    clock.advance(Duration::from_secs(30));
+    let stale = cache
+        .get_with_deadline(Duration::from_millis(10))
+        .await
+        .unwrap();
+    assert!(stale.is_stale());
+    assert_eq!(cache.status().phase, CachePhase::Fetching);
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(*source.fetched.lock().unwrap(), 2);
+    assert!(!cache.peek().unwrap().is_stale());
+}
+
+#[tokio::test]
+async fn test_empty_key_set() {
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::ZERO);
+    source.jwks.lock().unwrap().keys.clear();
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    assert!(matches!(
+        cache.get().await,
+        Err(RequestError::Source {
+            error: AttemptError::EmptyKeySet,
+            ..
+        })
+    ));
+    assert_eq!(
+        *source.fetched.lock().unwrap(),
+        1,
+        "Empty key set should not be retried"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_revalidation_keeps_content() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(20), Duration::ZERO).with_etag("v1");
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(10)))
+        .timeout_spec(stale_test_timeouts())
+        .refresh_on_unknown_kid(Duration::from_secs(60))
+        .negative_cache(Duration::from_secs(60), 10)
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    let first = cache.get_snapshot().await.unwrap();
+    advance(&clock, Duration::from_millis(30)).await;
+    let revalidated = cache.get_snapshot().await.unwrap();
+
+    assert_eq!(*source.not_modified.lock().unwrap(), 1);
+    assert!(Arc::ptr_eq(
+        &first.clone().into_jwks(),
+        &revalidated.clone().into_jwks()
+    ));
+    assert!(core::ptr::eq(
+        first.unknown_kids().unwrap(),
+        revalidated.unknown_kids().unwrap()
+    ));
+    assert!(revalidated.expires() > first.expires());
+
+    source.rotate_key("rotated");
+    advance(&clock, Duration::from_millis(30)).await;
+    let modified = cache.get_snapshot().await.unwrap();
+
+    assert_eq!(*source.not_modified.lock().unwrap(), 1);
+    assert!(modified.get_key("rotated").is_some());
+}
+
+/// Runtime which drops spawned tasks without ever running them
+struct DroppingRuntime;
+
+impl Runtime for DroppingRuntime {
+    fn spawn(&self, _task: BoxFuture<'static, ()>) {}
 
-        Ok((self.jwks.clone(), now + self.expires))
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
     }
 }
 
+#[tokio::test]
+async fn test_fetch_dropped_by_runtime() {
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .source(source.clone())
+        .runtime(DroppingRuntime)
+        .build()
+        .unwrap();
+
+    assert!(matches!(cache.get().await, Err(RequestError::Aborted)));
+    assert_eq!(cache.status().phase, CachePhase::Empty);
+}
+
 #[tokio::test]
 async fn test_fetch_concurrent_from_empty() {
     let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::ZERO);
-    let cache = CachedJWKS::from_source(
-        "https://example.com".parse().unwrap(),
-        false,
-        Duration::from_secs(60),
-        Default::default(),
-        source.clone(),
-    );
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .build()
+        .unwrap();
 
     const N: usize = 10;
     let mut tasks = tokio::task::JoinSet::new();
@@ -114,29 +585,120 @@ async fn test_fetch_concurrent_from_empty() {
     );
 }
 
+#[test]
+#[allow(deprecated)]
+fn test_deprecated_new() {
+    // built outside of a runtime
+    let cache = CachedJWKS::new(
+        "https://example.com".parse().unwrap(),
+        Duration::from_secs(60),
+        TimeoutSpec::default(),
+    )
+    .unwrap();
+
+    assert!(!cache.pkeys);
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_deprecated_new_rsa_pkeys() {
+    let cache = CachedJWKS::new_rsa_pkeys(
+        "https://example.com".parse().unwrap(),
+        Duration::from_secs(60),
+        TimeoutSpec {
+            deadline: Duration::ZERO,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    assert!(cache.pkeys);
+}
+
 #[tokio::test]
-async fn test_background_refresh_and_expire() {
-    let source = JwksSourceMock::new(Duration::from_millis(20), Duration::ZERO);
+#[allow(deprecated)]
+async fn test_deprecated_from_source() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::ZERO);
     let cache = CachedJWKS::from_source(
         "https://example.com".parse().unwrap(),
         false,
-        Duration::from_millis(10),
+        Duration::from_secs(60),
+        Default::default(),
+        source.clone(),
+    );
+
+    assert_eq!(cache.get().await.unwrap().keys.len(), 1);
+    assert_eq!(*source.fetched.lock().unwrap(), 1);
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_deprecated_from_source_outlives_runtime() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::ZERO);
+    let built_in = tokio::runtime::Runtime::new().unwrap();
+    let cache = built_in.block_on(async {
+        CachedJWKS::from_source(
+            "https://example.com".parse().unwrap(),
+            false,
+            Duration::from_secs(60),
+            Default::default(),
+            source.clone(),
+        )
+    });
+    drop(built_in);
+
+    let used_in = tokio::runtime::Runtime::new().unwrap();
+    let jwks = used_in
+        .block_on(async { tokio::time::timeout(Duration::from_secs(5), cache.get()).await })
+        .expect("Fetch should run on the runtime the cache is used in")
+        .unwrap();
+
+    assert_eq!(jwks.keys.len(), 1);
+    assert_eq!(*source.fetched.lock().unwrap(), 1);
+}
+
+#[test]
+#[allow(deprecated)]
+#[should_panic(expected = "Update period should be greater than timeout deadline")]
+fn test_deprecated_update_period_within_deadline() {
+    let _ = CachedJWKS::from_source(
+        "https://example.com".parse().unwrap(),
+        false,
+        Duration::from_secs(1),
         TimeoutSpec {
-            retries: 0,
-            retry_after: Duration::from_millis(1),
-            backoff: Duration::ZERO,
-            deadline: Duration::from_millis(1),
+            deadline: Duration::from_secs(1),
+            ..Default::default()
         },
-        source.clone(),
+        JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::ZERO),
     );
+}
+
+#[tokio::test]
+async fn test_background_refresh_and_expire() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_secs(20), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(10)))
+        .timeout_spec(TimeoutSpec {
+            retries: 0,
+            retry_after: Duration::from_secs(1),
+            backoff: Duration::ZERO,
+            deadline: Duration::from_secs(1),
+        })
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
 
     cache.get().await.unwrap();
     cache.get().await.unwrap();
-    tokio::time::sleep(Duration::from_millis(10)).await;
+    clock.advance(Duration::from_secs(10));
     cache.get().await.unwrap();
     cache.get().await.unwrap();
     cache.get().await.unwrap();
-    tokio::time::sleep(Duration::from_millis(1)).await;
+    // let background refresh run
+    tokio::task::yield_now().await;
 
     assert_eq!(
         source.fetched.lock().unwrap().clone(),
@@ -144,7 +706,7 @@ async fn test_background_refresh_and_expire() {
         "Should only performed fetch IO in background"
     );
 
-    tokio::time::sleep(Duration::from_millis(30)).await;
+    clock.advance(Duration::from_secs(30));
     cache.get().await.unwrap();
     cache.get().await.unwrap();
 
@@ -155,34 +717,1556 @@ async fn test_background_refresh_and_expire() {
     );
 }
 
-#[tokio::test]
-async fn test_timeout_policy() {
-    let source = JwksSourceMock::new(Duration::from_millis(300), Duration::from_millis(100));
-    let cache = CachedJWKS::from_source(
-        "https://example.com".parse().unwrap(),
-        false,
-        Duration::from_millis(200),
-        TimeoutSpec {
+#[tokio::test(start_paused = true)]
+async fn test_retry_policy() {
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .retry_policy(RetryPolicy {
             retries: 3,
-            retry_after: Duration::from_millis(10),
-            backoff: Duration::from_millis(1),
-            deadline: Duration::from_millis(50),
-        },
-        source.clone(),
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            jitter: true,
+            max_retry_after: Duration::from_millis(5),
+        })
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    source.fail_with(Some(MockError::NotFound));
+    cache.get().await.unwrap_err();
+
+    assert_eq!(
+        *source.fetched.lock().unwrap(),
+        1,
+        "Permanent errors should not be retried"
     );
 
-    let _err = cache
-        .get()
-        .await
-        .expect_err("Expected timeout to be reached");
+    source.fail_with(Some(MockError::Unavailable));
+    let err = cache.get().await.unwrap_err();
 
+    assert_eq!(*source.fetched.lock().unwrap(), 5);
+    assert!(matches!(
+        &err,
+        RequestError::Source { error: AttemptError::Client(MockError::Unavailable), retried }
+            if retried.len() == 3
+    ));
+    assert_eq!(err.attempts().count(), 4);
+
+    source.fail_with(Some(MockError::Throttled));
+    let started = tokio::time::Instant::now();
+    cache.get().await.unwrap_err();
+
+    assert_eq!(*source.fetched.lock().unwrap(), 9);
     assert!(
-        matches!(RequestError::<()>::Timeout, _err),
-        "Expected timeout error"
+        started.elapsed() >= Duration::from_millis(15),
+        "Waiting asked for by the source should be capped, but still respected"
+    );
+}
+
+#[tokio::test]
+async fn test_circuit_breaker() {
+    let clock = Arc::new(ManualClock::new());
+    let transitions = Arc::new(Mutex::new(Vec::new()));
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .retry_policy(RetryPolicy {
+            retries: 0,
+            ..Default::default()
+        })
+        .circuit_breaker(CircuitBreakerSpec {
+            failure_threshold: 2,
+            cool_down: Duration::from_secs(10),
+        })
+        .on_circuit_change({
+            let transitions = transitions.clone();
+            move |state| transitions.lock().unwrap().push(state)
+        })
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    source.set_failing(true);
+    cache.get().await.unwrap_err();
+    cache.get().await.unwrap_err();
+
+    assert!(matches!(
+        cache.get().await,
+        Err(RequestError::CircuitOpen { attempts }) if attempts.is_empty()
+    ));
+    assert_eq!(
+        *source.fetched.lock().unwrap(),
+        2,
+        "Source should not be called while the circuit is open"
     );
+    assert_eq!(cache.circuit_state(), Some(CircuitState::Open));
+
+    clock.advance(Duration::from_secs(10));
+    source.set_failing(false);
+    cache.get().await.unwrap();
+
+    assert_eq!(cache.circuit_state(), Some(CircuitState::Closed));
     assert_eq!(
-        source.fetched.lock().unwrap().clone(),
-        4, // initial request + 3 retries
-        "Should have retried 3 times"
+        *transitions.lock().unwrap(),
+        [
+            CircuitState::Open,
+            CircuitState::HalfOpen,
+            CircuitState::Closed
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_on_permanent_errors() {
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .circuit_breaker(CircuitBreakerSpec {
+            failure_threshold: 2,
+            cool_down: Duration::from_secs(10),
+        })
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    source.fail_with(Some(MockError::NotFound));
+    cache.get().await.unwrap_err();
+    cache.get().await.unwrap_err();
+
+    assert_eq!(
+        cache.circuit_state(),
+        Some(CircuitState::Open),
+        "Source answering with permanent errors should count as failing"
+    );
+    assert!(matches!(
+        cache.get().await,
+        Err(RequestError::CircuitOpen { .. })
+    ));
+    assert_eq!(*source.fetched.lock().unwrap(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_circuit_breaker_on_deadline() {
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::from_secs(60 * 60));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .timeout_spec(TimeoutSpec {
+            retries: 0,
+            retry_after: Duration::from_secs(10),
+            backoff: Duration::ZERO,
+            deadline: Duration::from_secs(1),
+        })
+        .circuit_breaker(CircuitBreakerSpec {
+            failure_threshold: 1,
+            cool_down: Duration::from_secs(60),
+        })
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    assert!(matches!(
+        cache.get().await,
+        Err(RequestError::Timeout { .. })
+    ));
+    assert_eq!(
+        cache.circuit_state(),
+        Some(CircuitState::Open),
+        "Attempt cut off by the deadline should count as failed"
+    );
+}
+
+#[test]
+fn test_retry_policy_backoff() {
+    let policy = RetryPolicy {
+        retries: 10,
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_secs(1),
+        jitter: false,
+        max_retry_after: Duration::from_secs(30),
+    };
+
+    assert_eq!(policy.delay(1, None), Duration::from_millis(100));
+    assert_eq!(policy.delay(3, None), Duration::from_millis(400));
+    assert_eq!(policy.delay(5, None), Duration::from_secs(1));
+    assert_eq!(policy.delay(200, None), Duration::from_secs(1));
+    assert_eq!(
+        policy.delay(1, Some(Duration::from_secs(60))),
+        Duration::from_secs(30)
+    );
+
+    let policy = RetryPolicy {
+        jitter: true,
+        ..policy
+    };
+    for _ in 0..100 {
+        assert!(policy.delay(3, None) <= Duration::from_millis(400));
+    }
+
+    let legacy = RetryPolicy::from(TimeoutSpec {
+        retries: 2,
+        backoff: Duration::from_millis(50),
+        ..Default::default()
+    });
+    assert_eq!(legacy.retries, 2);
+    assert_eq!(legacy.delay(4, None), Duration::from_millis(50));
+}
+
+#[test]
+fn test_http_error_classification() {
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_445_412_400);
+    let status = |status: u16, retry_after: Option<&str>| {
+        let mut headers = http::HeaderMap::new();
+        if let Some(retry_after) = retry_after {
+            headers.insert(http::header::RETRY_AFTER, retry_after.parse().unwrap());
+        }
+
+        HttpError::Status {
+            status: http::StatusCode::from_u16(status).unwrap(),
+            headers,
+        }
+    };
+    let classify = |error| <reqwest::Client as JwksSource>::classify(&error, now);
+
+    assert_eq!(classify(status(404, None)), ErrorClass::Permanent);
+    assert_eq!(
+        classify(HttpError::Certificate(CertificateError::NotRsa {
+            kid: "kid".to_owned()
+        })),
+        ErrorClass::Permanent
+    );
+    assert_eq!(classify(status(403, Some("10"))), ErrorClass::Permanent);
+    assert_eq!(
+        classify(status(500, Some("10"))),
+        ErrorClass::Transient { retry_after: None }
+    );
+    assert_eq!(
+        classify(status(429, Some("10"))),
+        ErrorClass::Transient {
+            retry_after: Some(Duration::from_secs(10))
+        }
+    );
+    assert_eq!(
+        classify(status(503, Some("Wed, 21 Oct 2015 07:28:00 GMT"))),
+        ErrorClass::Transient {
+            retry_after: Some(Duration::from_secs(80))
+        }
+    );
+    assert_eq!(
+        classify(status(503, Some("soon"))),
+        ErrorClass::Transient { retry_after: None }
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_timeout_policy() {
+    let source = JwksSourceMock::new(Duration::from_millis(300), Duration::from_millis(100));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(200)))
+        .timeout_spec(TimeoutSpec {
+            retries: 3,
+            retry_after: Duration::from_millis(10),
+            backoff: Duration::from_millis(1),
+            deadline: Duration::from_millis(50),
+        })
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    let err = cache
+        .get()
+        .await
+        .expect_err("Expected timeout to be reached");
+
+    // four attempts timing out take 43ms, within the deadline
+    assert!(
+        matches!(
+            &err,
+            RequestError::Source {
+                error: AttemptError::Timeout,
+                retried,
+            } if retried.len() == 3
+        ),
+        "Expected last attempt to time out after 3 retries, got {err:?}"
+    );
+    assert!(
+        err.attempts()
+            .all(|attempt| matches!(attempt, AttemptError::Timeout))
+    );
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        4, // initial request + 3 retries
+        "Should have retried 3 times"
+    );
+}
+
+fn stale_test_timeouts() -> TimeoutSpec {
+    TimeoutSpec {
+        retries: 0,
+        retry_after: Duration::from_millis(5),
+        backoff: Duration::ZERO,
+        deadline: Duration::from_millis(5),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_serve_stale_on_error() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(20), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(10)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .max_stale(Duration::from_millis(100))
+        .retry_schedule(RetrySchedule {
+            initial: Duration::ZERO,
+            max: Duration::ZERO,
+        })
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    let fresh = cache.get_snapshot().await.unwrap();
+    assert!(!fresh.is_stale());
+
+    source.set_failing(true);
+    advance(&clock, Duration::from_millis(30)).await;
+
+    let stale = cache.get_snapshot().await.unwrap();
+    assert!(stale.is_stale(), "Expected stale JWKS to be served");
+    assert_eq!(stale.jwks().keys.len(), 1);
+    assert_eq!(stale.expires(), fresh.expires());
+
+    // background retries keep failing, stale content is still served
+    cache.get_snapshot().await.unwrap();
+    advance(&clock, Duration::from_millis(1)).await;
+    assert!(cache.get_snapshot().await.unwrap().is_stale());
+
+    source.set_failing(false);
+    advance(&clock, Duration::from_millis(1)).await;
+    cache.get_snapshot().await.unwrap();
+    advance(&clock, Duration::from_millis(1)).await;
+
+    assert!(
+        !cache.get_snapshot().await.unwrap().is_stale(),
+        "Expected background retry to recover"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_stale_window_runs_out() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(20), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(10)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .max_stale(Duration::from_millis(100))
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+
+    source.set_failing(true);
+    advance(&clock, Duration::from_millis(30)).await;
+    assert!(cache.get_snapshot().await.unwrap().is_stale());
+
+    advance(&clock, Duration::from_millis(100)).await;
+    cache
+        .get()
+        .await
+        .expect_err("Stale JWKS should not be served past its stale window");
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_no_stale_by_default() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(20), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(10)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+
+    source.set_failing(true);
+    advance(&clock, Duration::from_millis(30)).await;
+
+    cache
+        .get()
+        .await
+        .expect_err("Expired JWKS should not be served without stale window");
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_hooks() {
+    let clock = Arc::new(ManualClock::new());
+    let updates = Arc::new(Mutex::new(0));
+    let errors = Arc::new(Mutex::new(0));
+    let source = JwksSourceMock::new(Duration::from_millis(20), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(10)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .on_update({
+            let updates = updates.clone();
+            move |snapshot| {
+                assert_eq!(snapshot.jwks().keys.len(), 1);
+                *updates.lock().unwrap() += 1;
+            }
+        })
+        .on_error({
+            let errors = errors.clone();
+            move |_| *errors.lock().unwrap() += 1
+        })
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+    assert_eq!(*updates.lock().unwrap(), 1);
+
+    source.set_failing(true);
+    advance(&clock, Duration::from_millis(30)).await;
+    cache.get().await.unwrap_err();
+
+    assert_eq!(*updates.lock().unwrap(), 1);
+    assert_eq!(*errors.lock().unwrap(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_stale_if_error_directive() {
+    let clock = Arc::new(ManualClock::new());
+    let mut source = JwksSourceMock::new(Duration::from_millis(20), Duration::ZERO);
+    source.stale_if_error = Some(Duration::from_secs(60));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(10)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+
+    source.set_failing(true);
+    advance(&clock, Duration::from_millis(30)).await;
+
+    assert!(cache.get_snapshot().await.unwrap().is_stale());
+}
+
+#[tokio::test]
+async fn test_stale_while_revalidate_directive() {
+    let clock = Arc::new(ManualClock::new());
+    let mut source = JwksSourceMock::new(Duration::from_secs(20), Duration::ZERO);
+    source.stale_while_revalidate = Some(Duration::from_secs(60));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(10)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+    clock.advance(Duration::from_secs(15));
+
+    assert!(!cache.get_snapshot().await.unwrap().is_stale());
+    tokio::task::yield_now().await;
+    assert_eq!(
+        *source.fetched.lock().unwrap(),
+        1,
+        "Directive should replace the configured refresh window"
+    );
+
+    clock.advance(Duration::from_secs(30));
+
+    assert!(
+        cache.get_snapshot().await.unwrap().is_stale(),
+        "Expired JWKS should be served while it is being revalidated"
+    );
+    // let background refresh run
+    tokio::task::yield_now().await;
+
+    assert_eq!(*source.fetched.lock().unwrap(), 2);
+    assert!(!cache.get_snapshot().await.unwrap().is_stale());
+
+    clock.advance(Duration::from_secs(90));
+
+    assert!(
+        !cache.get_snapshot().await.unwrap().is_stale(),
+        "JWKS past the revalidation window should be fetched"
+    );
+    assert_eq!(*source.fetched.lock().unwrap(), 3);
+}
+
+#[test]
+fn test_cache_control_directive() {
+    let mut headers = http::HeaderMap::new();
+    headers.append(
+        http::header::CACHE_CONTROL,
+        "public, max-age=300".parse().unwrap(),
+    );
+    headers.append(
+        http::header::CACHE_CONTROL,
+        "Stale-If-Error=\"600\", stale-while-revalidate=30"
+            .parse()
+            .unwrap(),
+    );
+
+    assert_eq!(
+        super::cache_control_directive(&headers, "max-age"),
+        Some(Duration::from_secs(300))
+    );
+    assert_eq!(
+        super::cache_control_directive(&headers, "stale-if-error"),
+        Some(Duration::from_secs(600))
+    );
+    assert_eq!(super::cache_control_directive(&headers, "s-maxage"), None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_failed_refresh_does_not_outlive_expiration() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(100), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(50)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .retry_schedule(RetrySchedule {
+            initial: Duration::from_secs(60),
+            max: Duration::from_secs(60),
+        })
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+
+    source.set_failing(true);
+    advance(&clock, Duration::from_millis(60)).await;
+
+    // refresh window, background refresh fails
+    cache.get().await.unwrap();
+    advance(&clock, Duration::from_millis(1)).await;
+    cache.get().await.unwrap();
+
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        2,
+        "Should wait for retry schedule before retrying"
+    );
+
+    advance(&clock, Duration::from_millis(50)).await;
+
+    cache
+        .get()
+        .await
+        .expect_err("Expired JWKS should not be served after failed background refresh");
+    assert_eq!(source.fetched.lock().unwrap().clone(), 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_failed_refresh_retry_schedule() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(300), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(200)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .retry_schedule(RetrySchedule {
+            initial: Duration::from_millis(20),
+            max: Duration::from_millis(20),
+        })
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+    source.set_failing(true);
+    advance(&clock, Duration::from_millis(110)).await;
+
+    // within refresh window, background refresh fails
+    cache.get().await.unwrap();
+    advance(&clock, Duration::from_millis(5)).await;
+    cache.get().await.unwrap();
+    assert_eq!(source.fetched.lock().unwrap().clone(), 2);
+
+    advance(&clock, Duration::from_millis(20)).await;
+    source.set_failing(false);
+    cache.get().await.unwrap();
+    advance(&clock, Duration::from_millis(5)).await;
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        3,
+        "Should retry once retry is due"
+    );
+
+    // refreshed content is fresh, no more refreshing needed until its refresh window
+    cache.get().await.unwrap();
+    assert_eq!(source.fetched.lock().unwrap().clone(), 3);
+}
+
+#[test]
+fn test_retry_schedule_delay() {
+    let schedule = RetrySchedule {
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(10),
+    };
+
+    assert_eq!(schedule.delay(1), Duration::from_secs(1));
+    assert_eq!(schedule.delay(2), Duration::from_secs(2));
+    assert_eq!(schedule.delay(4), Duration::from_secs(8));
+    assert_eq!(schedule.delay(5), Duration::from_secs(10));
+    assert_eq!(schedule.delay(100), Duration::from_secs(10));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_fetch_survives_cancelled_caller() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::from_millis(50));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    tokio::time::timeout(Duration::from_millis(10), cache.get())
+        .await
+        .expect_err("Caller should be cancelled while fetch is ongoing");
+
+    let jwks = tokio::time::timeout(Duration::from_secs(1), cache.get())
+        .await
+        .expect("Follow up caller should not hang")
+        .unwrap();
+
+    assert_eq!(jwks.keys.len(), 1);
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        1,
+        "Follow up caller should join the fetch started by cancelled caller"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_waiters_released_when_leader_cancelled() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::from_millis(50));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    let leader = tokio::spawn({
+        let cache = cache.clone();
+        async move { cache.get().await }
+    });
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let mut waiters = tokio::task::JoinSet::new();
+    for _ in 0..5 {
+        let cache = cache.clone();
+        waiters.spawn(async move { cache.get().await.unwrap() });
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    leader.abort();
+
+    let results = tokio::time::timeout(Duration::from_secs(1), waiters.join_all())
+        .await
+        .expect("Waiters should not hang after leader got cancelled");
+
+    for r in results {
+        assert_eq!(r.keys.len(), 1);
+    }
+
+    assert_eq!(source.fetched.lock().unwrap().clone(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_stress_concurrent_refetching() {
+    // content expires immediately, so callers keep contending on fetching
+    let source = JwksSourceMock::new(Duration::ZERO, Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    const TASKS: usize = 64;
+    const ITERATIONS: usize = 20;
+    let mut tasks = tokio::task::JoinSet::new();
+
+    for _ in 0..TASKS {
+        let cache = cache.clone();
+        tasks.spawn(async move {
+            for _ in 0..ITERATIONS {
+                let jwks = cache.get().await.unwrap();
+                assert_eq!(jwks.keys.len(), 1);
+            }
+        });
+    }
+
+    tokio::time::timeout(Duration::from_secs(30), tasks.join_all())
+        .await
+        .expect("No caller should miss conclusion of fetching");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_stress_concurrent_failing() {
+    let source = JwksSourceMock::new(Duration::ZERO, Duration::ZERO);
+    source.set_failing(true);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    const TASKS: usize = 64;
+    const ITERATIONS: usize = 20;
+    let mut tasks = tokio::task::JoinSet::new();
+
+    for _ in 0..TASKS {
+        let cache = cache.clone();
+        tasks.spawn(async move {
+            for _ in 0..ITERATIONS {
+                cache.get().await.expect_err("Source is failing");
+            }
+        });
+    }
+
+    tokio::time::timeout(Duration::from_secs(30), tasks.join_all())
+        .await
+        .expect("No caller should miss conclusion of fetching");
+}
+
+#[tokio::test]
+async fn test_snapshot_shared_between_reads() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    let first = cache.get().await.unwrap();
+    let second = cache.get().await.unwrap();
+    let snapshot = cache.get_snapshot().await.unwrap();
+
+    assert!(
+        Arc::ptr_eq(&first, &second),
+        "Reads should share the cached JWK Set"
+    );
+    assert!(Arc::ptr_eq(&first, &snapshot.into_jwks()));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_contending_callers_join_fetch() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::from_millis(20));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    const N: usize = 256;
+    let mut tasks = tokio::task::JoinSet::new();
+    let barrier = Arc::new(tokio::sync::Barrier::new(N));
+
+    for _ in 0..N {
+        let barrier = barrier.clone();
+        let cache = cache.clone();
+        tasks.spawn(async move {
+            barrier.wait().await;
+
+            cache.get().await.unwrap()
+        });
+    }
+
+    let results = tokio::time::timeout(Duration::from_secs(5), tasks.join_all())
+        .await
+        .expect("Callers losing the election should wait for the ongoing fetch");
+
+    for r in results {
+        assert_eq!(r.keys.len(), 1);
+    }
+
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        1,
+        "Should only performed fetch IO once"
+    );
+}
+
+#[tokio::test]
+async fn test_key_lookup() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    let jwk = cache.get_key("2011-04-29").await.unwrap();
+    assert_eq!(jwk.common.key_id.as_deref(), Some("2011-04-29"));
+
+    let found = cache
+        .find("2011-04-29", jsonwebtoken::Algorithm::RS256)
+        .await
+        .unwrap();
+    assert!(
+        Arc::ptr_eq(&jwk, &found),
+        "Lookups should share indexed key"
+    );
+
+    assert!(matches!(
+        cache.get_key("unknown").await,
+        Err(LookupError::KeyNotFound)
+    ));
+    assert!(matches!(
+        cache
+            .find("2011-04-29", jsonwebtoken::Algorithm::ES256)
+            .await,
+        Err(LookupError::KeyNotFound)
+    ));
+
+    source.set_failing(true);
+    let failing = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .build()
+        .unwrap();
+    assert!(matches!(
+        failing.get_key("2011-04-29").await,
+        Err(LookupError::Request(RequestError::Source {
+            error: AttemptError::Client(MockError::Unavailable),
+            ..
+        }))
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_refresh_on_unknown_kid() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .refresh_on_unknown_kid(Duration::from_millis(50))
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get_key("2011-04-29").await.unwrap();
+
+    source.rotate_key("rotated");
+    let jwk = cache.get_key("rotated").await.unwrap();
+    assert_eq!(jwk.common.key_id.as_deref(), Some("rotated"));
+    assert_eq!(source.fetched.lock().unwrap().clone(), 2);
+
+    assert!(matches!(
+        cache.get_key("made-up").await,
+        Err(LookupError::KeyNotFound)
+    ));
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        2,
+        "Forced refreshes should be rate limited"
+    );
+
+    advance(&clock, Duration::from_millis(50)).await;
+    assert!(matches!(
+        cache.get_key("made-up").await,
+        Err(LookupError::KeyNotFound)
+    ));
+    assert_eq!(source.fetched.lock().unwrap().clone(), 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_refresh_on_unknown_kid_joins_refresh() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::from_secs(1));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(30)))
+        .refresh_on_unknown_kid(Duration::from_secs(1))
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+
+    clock.advance(Duration::from_secs(40));
+    cache.try_get().unwrap();
+    source.rotate_key("rotated");
+
+    cache.get_key("rotated").await.unwrap();
+    assert_eq!(
+        *source.fetched.lock().unwrap(),
+        2,
+        "Lookup should join the ongoing refresh instead of fetching again"
+    );
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert!(cache.peek().unwrap().get_key("rotated").is_some());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_concurrent_lookups_join_refresh() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::from_secs(1));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(30)))
+        .refresh_on_unknown_kid(Duration::from_secs(1))
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+
+    clock.advance(Duration::from_secs(40));
+    cache.try_get().unwrap();
+    source.rotate_key("rotated");
+
+    let (first, second) = tokio::join!(cache.get_key("rotated"), cache.get_key("rotated"));
+    assert!(first.is_ok());
+    assert!(
+        second.is_ok(),
+        "Lookup denied the forced refresh should wait for the one in flight"
+    );
+    assert_eq!(*source.fetched.lock().unwrap(), 2);
+}
+
+#[tokio::test]
+async fn test_failed_refresh_on_unknown_kid() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(30)))
+        .retry_policy(RetryPolicy {
+            retries: 0,
+            ..Default::default()
+        })
+        .refresh_on_unknown_kid(Duration::from_secs(1))
+        .negative_cache(Duration::from_secs(60), 10)
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+
+    source.set_failing(true);
+    assert!(matches!(
+        cache.get_key("rotated").await,
+        Err(LookupError::Request(RequestError::Source { .. }))
+    ));
+
+    source.set_failing(false);
+    source.rotate_key("rotated");
+    clock.advance(Duration::from_secs(1));
+    cache.get_key("rotated").await.unwrap();
+    assert_eq!(*source.fetched.lock().unwrap(), 3);
+}
+
+#[tokio::test]
+async fn test_no_refresh_on_unknown_kid_by_default() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .build()
+        .unwrap();
+
+    cache.get_key("2011-04-29").await.unwrap();
+
+    source.rotate_key("rotated");
+    assert!(matches!(
+        cache.get_key("rotated").await,
+        Err(LookupError::KeyNotFound)
+    ));
+    assert_eq!(source.fetched.lock().unwrap().clone(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_refresh_on_unknown_kid() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::from_millis(20));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .refresh_on_unknown_kid(Duration::from_secs(60))
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+    source.rotate_key("rotated");
+
+    const N: usize = 32;
+    let mut tasks = tokio::task::JoinSet::new();
+    let barrier = Arc::new(tokio::sync::Barrier::new(N));
+
+    for _ in 0..N {
+        let barrier = barrier.clone();
+        let cache = cache.clone();
+        tasks.spawn(async move {
+            barrier.wait().await;
+
+            cache.get_key("rotated").await
+        });
+    }
+
+    for result in tasks.join_all().await {
+        assert!(result.is_ok(), "Every lookup should find the rotated key");
+    }
+
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        2,
+        "Should only force a single refresh"
+    );
+}
+
+#[tokio::test]
+async fn test_negative_cache() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .refresh_on_unknown_kid(Duration::ZERO)
+        .negative_cache(Duration::from_secs(60), 100)
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+
+    assert!(matches!(
+        cache.get_key("made-up").await,
+        Err(LookupError::KeyNotFound)
+    ));
+    assert_eq!(source.fetched.lock().unwrap().clone(), 2);
+
+    assert!(matches!(
+        cache.get_key("made-up").await,
+        Err(LookupError::KeyNotFound)
+    ));
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        2,
+        "Known unknown key ID should be rejected without refreshing"
+    );
+
+    source.rotate_key("rotated");
+    cache.get_key("rotated").await.unwrap();
+    assert_eq!(source.fetched.lock().unwrap().clone(), 3);
+
+    // newly fetched JWK Set starts with no remembered key IDs
+    source.rotate_key("made-up");
+    cache.get_key("made-up").await.unwrap();
+    assert_eq!(source.fetched.lock().unwrap().clone(), 4);
+}
+
+#[tokio::test]
+async fn test_min_ttl() {
+    let source = JwksSourceMock::new(Duration::ZERO, Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .ttl_spec(TtlSpec {
+            min_ttl: Duration::from_secs(60 * 60),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+    cache.get().await.unwrap();
+
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        1,
+        "JWK Set should be cached for at least min TTL"
+    );
+}
+
+#[tokio::test]
+async fn test_max_ttl() {
+    let source = JwksSourceMock::new(Duration::from_secs(60 * 60 * 24 * 365), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .ttl_spec(TtlSpec {
+            max_ttl: Duration::from_secs(60 * 60),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+
+    let now = SystemTime::now();
+    let snapshot = cache.get_snapshot().await.unwrap();
+
+    assert!(snapshot.expires() <= now + Duration::from_secs(60 * 60 + 1));
+}
+
+#[tokio::test]
+async fn test_default_ttl() {
+    let mut source = JwksSourceMock::new(Duration::ZERO, Duration::ZERO);
+    source.expires = None;
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(60)))
+        .source(source.clone())
+        .ttl_spec(TtlSpec {
+            default_ttl: Duration::from_secs(60 * 60),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+    cache.get().await.unwrap();
+
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        1,
+        "JWK Set without freshness information should be cached for default TTL"
+    );
+}
+
+#[test]
+fn test_ttl_spec() {
+    let spec = TtlSpec {
+        min_ttl: Duration::from_secs(60),
+        max_ttl: Duration::from_secs(600),
+        default_ttl: Duration::from_secs(300),
+    };
+    let now = SystemTime::now();
+
+    assert_eq!(spec.ttl(now, Some(now)), Duration::from_secs(60));
+    assert_eq!(
+        spec.ttl(now, Some(now - Duration::from_secs(10))),
+        Duration::from_secs(60)
+    );
+    assert_eq!(
+        spec.ttl(now, Some(now + Duration::from_secs(120))),
+        Duration::from_secs(120)
+    );
+    assert_eq!(
+        spec.ttl(now, Some(now + Duration::from_secs(6000))),
+        Duration::from_secs(600)
+    );
+    assert_eq!(spec.ttl(now, None), Duration::from_secs(300));
+}
+
+#[test]
+fn test_explicit_freshness() {
+    let mut headers = http::HeaderMap::new();
+    assert!(!super::has_explicit_freshness(&headers));
+
+    headers.insert(http::header::CACHE_CONTROL, "public".parse().unwrap());
+    assert!(!super::has_explicit_freshness(&headers));
+
+    headers.insert(
+        http::header::CACHE_CONTROL,
+        "public, Max-Age=0".parse().unwrap(),
+    );
+    assert!(super::has_explicit_freshness(&headers));
+
+    headers.insert(http::header::CACHE_CONTROL, "no-store".parse().unwrap());
+    assert!(super::has_explicit_freshness(&headers));
+
+    headers.remove(http::header::CACHE_CONTROL);
+    headers.insert(
+        http::header::EXPIRES,
+        "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+    );
+    assert!(super::has_explicit_freshness(&headers));
+}
+
+#[test]
+fn test_refresh_window() {
+    let lifetime = Duration::from_secs(100);
+
+    assert_eq!(
+        RefreshWindow::BeforeExpiry(Duration::from_secs(30)).refresh_after(lifetime),
+        Duration::from_secs(70)
+    );
+    assert_eq!(
+        RefreshWindow::BeforeExpiry(Duration::from_secs(300)).refresh_after(lifetime),
+        Duration::ZERO,
+        "refresh should not be scheduled before the JWK Set was fetched"
+    );
+    assert_eq!(
+        RefreshWindow::AfterLifetimeFraction(0.8).refresh_after(lifetime),
+        Duration::from_secs(80)
+    );
+    assert_eq!(
+        RefreshWindow::AfterLifetimeFraction(1.5).refresh_after(lifetime),
+        lifetime
+    );
+    assert_eq!(
+        RefreshWindow::AfterLifetimeFraction(f64::NAN).refresh_after(lifetime),
+        lifetime
+    );
+}
+
+#[tokio::test]
+async fn test_refresh_jitter() {
+    let source = JwksSourceMock::new(Duration::from_secs(100), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .source(source)
+        .refresh_window(RefreshWindow::AfterLifetimeFraction(0.8))
+        .refresh_jitter(0.1)
+        .build()
+        .unwrap();
+
+    let now = Instant::now();
+    let system_now = SystemTime::now();
+    for _ in 0..100 {
+        let response = JwksResponse::from((
+            serde_json::from_str(JWKS_SAMPLE).unwrap(),
+            system_now + Duration::from_secs(100),
+        ));
+        let cached = cache.cache_set(now, system_now, None, Revalidated::Modified(response));
+
+        assert!(cached.refresh_at <= now + Duration::from_secs(80));
+        assert!(cached.refresh_at >= now + Duration::from_secs(70));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_refresh_window_fraction() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(200), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .refresh_window(RefreshWindow::AfterLifetimeFraction(0.25))
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+    advance(&clock, Duration::from_millis(100)).await;
+    cache.get().await.unwrap();
+    advance(&clock, Duration::from_millis(20)).await;
+
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        2,
+        "JWK Set should be refreshed in the background past the lifetime fraction"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_refresher_keeps_cache_warm() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(100), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(50)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .retry_schedule(RetrySchedule {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(1),
+        })
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    let refresher = cache.spawn_refresher(None);
+
+    advance(&clock, Duration::from_millis(20)).await;
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        1,
+        "Refresher should fetch into empty cache without any traffic"
+    );
+
+    advance(&clock, Duration::from_millis(60)).await;
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        2,
+        "Refresher should refresh once refresh window is reached"
+    );
+
+    let snapshot = cache.get_snapshot().await.unwrap();
+    assert!(!snapshot.is_stale());
+    assert_eq!(source.fetched.lock().unwrap().clone(), 2);
+
+    refresher.stop();
+    advance(&clock, Duration::from_millis(100)).await;
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        2,
+        "Stopped refresher should not refresh anymore"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_refresher_gives_up_after_failures() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(100), Duration::ZERO);
+    source.set_failing(true);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(50)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .retry_schedule(RetrySchedule {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(4),
+        })
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    let refresher = cache.spawn_refresher(Some(3));
+
+    advance(&clock, Duration::from_millis(50)).await;
+    assert!(refresher.is_finished(), "Refresher should have given up");
+    assert_eq!(source.fetched.lock().unwrap().clone(), 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_refresher_keeps_going_after_failures() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(100), Duration::ZERO);
+    source.set_failing(true);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(50)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .retry_schedule(RetrySchedule {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(4),
+        })
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    let refresher = cache.spawn_refresher(None);
+
+    advance(&clock, Duration::from_millis(50)).await;
+    assert!(!refresher.is_finished());
+    assert!(*source.fetched.lock().unwrap() > 3);
+
+    source.set_failing(false);
+    advance(&clock, Duration::from_millis(20)).await;
+    assert!(
+        !cache.get_snapshot().await.unwrap().is_stale(),
+        "Refresher should have recovered once source works again"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_background_work_aborted_on_drop() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(100), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(50)))
+        .timeout_spec(stale_test_timeouts())
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+    let _refresher = cache.spawn_refresher(None);
+
+    // background refresh is in flight and never concludes in time
+    let mut slow_source = source.clone();
+    slow_source.take_time = Duration::from_secs(60);
+    let slow_cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(50)))
+        .timeout_spec(stale_test_timeouts())
+        .source(slow_source)
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+    let fetch = {
+        let slow_cache = slow_cache.clone();
+        tokio::spawn(async move { slow_cache.get().await })
+    };
+    advance(&clock, Duration::from_millis(1)).await;
+    fetch.abort();
+
+    drop(cache);
+    drop(slow_cache);
+    advance(&clock, Duration::from_millis(1)).await;
+
+    assert_eq!(
+        Arc::strong_count(&source.fetched),
+        1,
+        "Background work should not outlive the cache"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_shutdown_waits_for_refresh() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(100), Duration::from_millis(20));
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_millis(90)))
+        .timeout_spec(TimeoutSpec {
+            retries: 0,
+            retry_after: Duration::from_millis(50),
+            backoff: Duration::ZERO,
+            deadline: Duration::from_millis(50),
+        })
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+    advance(&clock, Duration::from_millis(15)).await;
+    cache.get().await.unwrap();
+    advance(&clock, Duration::from_millis(1)).await;
+    assert_eq!(source.fetched.lock().unwrap().clone(), 2);
+
+    assert!(cache.shutdown(Duration::from_millis(100)).await);
+    assert!(!cache.get_snapshot().await.unwrap().is_stale());
+
+    advance(&clock, Duration::from_millis(30)).await;
+    cache.get().await.unwrap();
+    advance(&clock, Duration::from_millis(30)).await;
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        2,
+        "No background refresh should be started after shutdown"
+    );
+
+    advance(&clock, Duration::from_millis(50)).await;
+    cache.get().await.unwrap();
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        3,
+        "Expired JWK Set should still be fetched after shutdown"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_shutdown_cancels_after_deadline() {
+    let clock = Arc::new(ManualClock::new());
+    let source = JwksSourceMock::new(Duration::from_millis(100), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(90)))
+        .timeout_spec(TimeoutSpec {
+            retries: 0,
+            retry_after: Duration::from_secs(60),
+            backoff: Duration::ZERO,
+            deadline: Duration::from_secs(60),
+        })
+        .source({
+            let mut source = source.clone();
+            source.take_time = Duration::from_secs(60);
+            source
+        })
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    let fetch = {
+        let cache = cache.clone();
+        tokio::spawn(async move { cache.get().await })
+    };
+    advance(&clock, Duration::from_millis(1)).await;
+    let refresher = cache.spawn_refresher(None);
+
+    assert!(!cache.shutdown(Duration::from_millis(10)).await);
+    assert!(refresher.is_finished());
+
+    // cancelled fetch has reset the state, so its caller fetches again by itself
+    advance(&clock, Duration::from_millis(1)).await;
+    assert_eq!(source.fetched.lock().unwrap().clone(), 2);
+    fetch.abort();
+}
+
+/// Wall-clock time jumps independently of the monotonic time
+struct JumpingClock {
+    clock: ManualClock,
+    jump: Mutex<Duration>,
+}
+
+impl Clock for JumpingClock {
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.clock.system_time() + *self.jump.lock().unwrap()
+    }
+}
+
+#[tokio::test]
+async fn test_wall_clock_jump() {
+    let clock = Arc::new(JumpingClock {
+        clock: ManualClock::new(),
+        jump: Mutex::new(Duration::ZERO),
+    });
+    let source = JwksSourceMock::new(Duration::from_secs(60), Duration::ZERO);
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(30)))
+        .source(source.clone())
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    cache.get().await.unwrap();
+
+    *clock.jump.lock().unwrap() = Duration::from_secs(60 * 60 * 24);
+    cache.get().await.unwrap();
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        1,
+        "Wall-clock jump should not expire cached JWK Set"
+    );
+
+    clock.clock.advance(Duration::from_secs(60));
+    cache.get().await.unwrap();
+    assert_eq!(
+        source.fetched.lock().unwrap().clone(),
+        2,
+        "JWK Set should expire on monotonic time"
     );
 }
@@ -0,0 +1,155 @@
+#[cfg(test)]
+mod test;
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Circuit breaker in front of the JWKS source, which stops calling a failing source for a while
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerSpec {
+    /// Consecutive failed attempts after which the circuit opens
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before a single probe is let through
+    pub cool_down: Duration,
+}
+
+impl Default for CircuitBreakerSpec {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cool_down: Duration::from_secs(30),
+        }
+    }
+}
+
+/// State of the circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Source is called as usual
+    Closed,
+    /// Source is failing and is not called until the cool-down passes
+    Open,
+    /// Cool-down has passed, a single probe is let through to tell whether the source recovered
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum Circuit {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probing: bool },
+}
+
+impl Circuit {
+    fn state(&self) -> CircuitState {
+        match self {
+            Self::Closed { .. } => CircuitState::Closed,
+            Self::Open { .. } => CircuitState::Open,
+            Self::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    spec: CircuitBreakerSpec,
+    circuit: Mutex<Circuit>,
+}
+
+/// Permission to call the source, its outcome is recorded by [`Permit::record`]. Probe which is
+/// dropped without recording its outcome lets another one through.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    recorded: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(spec: CircuitBreakerSpec) -> Self {
+        Self {
+            spec,
+            circuit: Mutex::new(Circuit::Closed { failures: 0 }),
+        }
+    }
+
+    fn circuit(&self) -> MutexGuard<'_, Circuit> {
+        self.circuit.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.circuit().state()
+    }
+
+    /// Permit calling the source at `now`, `None` when the circuit is open or its probe is ongoing.
+    /// Also tells the new state when the circuit changed it.
+    pub fn permit(&self, now: Instant) -> (Option<Permit<'_>>, Option<CircuitState>) {
+        let mut circuit = self.circuit();
+
+        let (permitted, transition) = match *circuit {
+            Circuit::Closed { .. } => (true, None),
+            Circuit::Open { until } if now >= until => {
+                *circuit = Circuit::HalfOpen { probing: true };
+
+                (true, Some(CircuitState::HalfOpen))
+            }
+            Circuit::HalfOpen { probing: false } => {
+                *circuit = Circuit::HalfOpen { probing: true };
+
+                (true, None)
+            }
+            Circuit::Open { .. } | Circuit::HalfOpen { probing: true } => (false, None),
+        };
+
+        drop(circuit);
+
+        let permit = permitted.then(|| Permit {
+            breaker: self,
+            recorded: false,
+        });
+
+        (permit, transition)
+    }
+
+    fn record(&self, success: bool, now: Instant) -> Option<CircuitState> {
+        let mut circuit = self.circuit();
+        let previous = circuit.state();
+
+        *circuit = match (&*circuit, success) {
+            (_, true) => Circuit::Closed { failures: 0 },
+            (Circuit::Closed { failures }, false) if failures + 1 < self.spec.failure_threshold => {
+                Circuit::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (Circuit::Open { until }, false) => Circuit::Open { until: *until },
+            (_, false) => Circuit::Open {
+                until: now + self.spec.cool_down,
+            },
+        };
+
+        let state = circuit.state();
+        (state != previous).then_some(state)
+    }
+}
+
+impl Permit<'_> {
+    /// Record whether the source was reached at `now`, tells the new state when the circuit changed it
+    pub fn record(mut self, success: bool, now: Instant) -> Option<CircuitState> {
+        self.recorded = true;
+
+        self.breaker.record(success, now)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.recorded {
+            return;
+        }
+
+        let mut circuit = self.breaker.circuit();
+        if let Circuit::HalfOpen { probing: true } = *circuit {
+            *circuit = Circuit::HalfOpen { probing: false };
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use super::{CircuitBreaker, CircuitBreakerSpec, CircuitState};
+use std::time::{Duration, Instant};
+
+fn breaker() -> CircuitBreaker {
+    CircuitBreaker::new(CircuitBreakerSpec {
+        failure_threshold: 2,
+        cool_down: Duration::from_secs(10),
+    })
+}
+
+#[test]
+fn test_opens_after_consecutive_failures() {
+    let now = Instant::now();
+    let breaker = breaker();
+
+    let (permit, _) = breaker.permit(now);
+    assert_eq!(permit.unwrap().record(false, now), None);
+    let (permit, _) = breaker.permit(now);
+    assert_eq!(permit.unwrap().record(true, now), None);
+    let (permit, _) = breaker.permit(now);
+    assert_eq!(permit.unwrap().record(false, now), None);
+    let (permit, _) = breaker.permit(now);
+    assert_eq!(permit.unwrap().record(false, now), Some(CircuitState::Open));
+
+    let (permit, transition) = breaker.permit(now + Duration::from_secs(5));
+    assert!(permit.is_none());
+    assert_eq!(transition, None);
+}
+
+#[test]
+fn test_half_open_probe() {
+    let now = Instant::now();
+    let breaker = breaker();
+    for _ in 0..2 {
+        breaker.permit(now).0.unwrap().record(false, now);
+    }
+
+    let later = now + Duration::from_secs(10);
+    let (probe, transition) = breaker.permit(later);
+    assert_eq!(transition, Some(CircuitState::HalfOpen));
+    assert!(
+        breaker.permit(later).0.is_none(),
+        "Only a single probe should be let through"
+    );
+
+    assert_eq!(
+        probe.unwrap().record(false, later),
+        Some(CircuitState::Open)
+    );
+    assert!(breaker.permit(later).0.is_none());
+
+    let later = later + Duration::from_secs(10);
+    let (probe, _) = breaker.permit(later);
+    assert_eq!(
+        probe.unwrap().record(true, later),
+        Some(CircuitState::Closed)
+    );
+    assert_eq!(breaker.state(), CircuitState::Closed);
+}
+
+#[test]
+fn test_dropped_probe() {
+    let now = Instant::now();
+    let breaker = breaker();
+    for _ in 0..2 {
+        breaker.permit(now).0.unwrap().record(false, now);
+    }
+
+    let later = now + Duration::from_secs(10);
+    drop(breaker.permit(later));
+
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    assert!(
+        breaker.permit(later).0.is_some(),
+        "Another probe should be let through once the previous one was dropped"
+    );
+}
@@ -0,0 +1,84 @@
+#[cfg(test)]
+mod test;
+
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of time for the cache. Lifetime of cached content is tracked on monotonic time, so wall-clock
+/// jumps do not affect it, while wall-clock time is only used for interpreting caching headers.
+pub trait Clock: Send + Sync + 'static {
+    /// Monotonic time
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time
+    fn system_time(&self) -> SystemTime;
+}
+
+/// Clock of the operating system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock which only moves when advanced, for deterministic tests. Timers of the runtime are not
+/// affected by it.
+#[derive(Debug)]
+pub struct ManualClock {
+    instant: Instant,
+    system_time: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// Clock starting at the current time
+    pub fn new() -> Self {
+        Self {
+            instant: Instant::now(),
+            system_time: SystemTime::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Move the clock forward
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap_or_else(PoisonError::into_inner) += duration;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.instant + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.system_time + self.elapsed()
+    }
+}
+
+impl<C: Clock> Clock for std::sync::Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        (**self).system_time()
+    }
+}
@@ -0,0 +1,16 @@
+use super::{Clock, ManualClock};
+use std::time::Duration;
+
+#[test]
+fn test_manual_clock() {
+    let clock = ManualClock::new();
+    let now = clock.now();
+    let system_time = clock.system_time();
+
+    assert_eq!(clock.now(), now, "Clock should only move when advanced");
+
+    clock.advance(Duration::from_secs(60));
+
+    assert_eq!(clock.now(), now + Duration::from_secs(60));
+    assert_eq!(clock.system_time(), system_time + Duration::from_secs(60));
+}
@@ -0,0 +1,86 @@
+#[cfg(test)]
+mod test;
+
+use jsonwebtoken::{
+    Algorithm,
+    jwk::{AlgorithmParameters, Jwk, JwkSet, KeyAlgorithm},
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Keys of a JWK Set indexed by their key ID, keys without key ID are not indexed
+#[derive(Debug, Default)]
+pub struct KeyIndex(HashMap<String, Vec<Arc<Jwk>>>);
+
+impl KeyIndex {
+    pub fn new(jwks: &JwkSet) -> Self {
+        let mut index: HashMap<String, Vec<Arc<Jwk>>> = HashMap::with_capacity(jwks.keys.len());
+
+        for jwk in &jwks.keys {
+            if let Some(kid) = &jwk.common.key_id {
+                index
+                    .entry(kid.clone())
+                    .or_default()
+                    .push(Arc::new(jwk.clone()));
+            }
+        }
+
+        Self(index)
+    }
+
+    /// First key with matching key ID
+    pub fn get(&self, kid: &str) -> Option<&Arc<Jwk>> {
+        self.0.get(kid)?.first()
+    }
+
+    /// First key with matching key ID which can be used with the algorithm
+    pub fn find(&self, kid: &str, alg: Algorithm) -> Option<&Arc<Jwk>> {
+        self.0
+            .get(kid)?
+            .iter()
+            .find(|jwk| supports_algorithm(jwk, alg))
+    }
+}
+
+/// Key either declares the algorithm or, when it does not, its type fits the algorithm
+fn supports_algorithm(jwk: &Jwk, alg: Algorithm) -> bool {
+    if let Some(key_algorithm) = jwk.common.key_algorithm {
+        return key_algorithm == to_key_algorithm(alg);
+    }
+
+    matches!(
+        (&jwk.algorithm, alg),
+        (
+            AlgorithmParameters::RSA(_),
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+        ) | (
+            AlgorithmParameters::EllipticCurve(_),
+            Algorithm::ES256 | Algorithm::ES384
+        ) | (
+            AlgorithmParameters::OctetKey(_),
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) | (AlgorithmParameters::OctetKeyPair(_), Algorithm::EdDSA)
+    )
+}
+
+fn to_key_algorithm(alg: Algorithm) -> KeyAlgorithm {
+    match alg {
+        Algorithm::HS256 => KeyAlgorithm::HS256,
+        Algorithm::HS384 => KeyAlgorithm::HS384,
+        Algorithm::HS512 => KeyAlgorithm::HS512,
+        Algorithm::ES256 => KeyAlgorithm::ES256,
+        Algorithm::ES384 => KeyAlgorithm::ES384,
+        Algorithm::RS256 => KeyAlgorithm::RS256,
+        Algorithm::RS384 => KeyAlgorithm::RS384,
+        Algorithm::RS512 => KeyAlgorithm::RS512,
+        Algorithm::PS256 => KeyAlgorithm::PS256,
+        Algorithm::PS384 => KeyAlgorithm::PS384,
+        Algorithm::PS512 => KeyAlgorithm::PS512,
+        Algorithm::EdDSA => KeyAlgorithm::EdDSA,
+    }
+}
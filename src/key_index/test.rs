@@ -0,0 +1,38 @@
+use super::KeyIndex;
+use jsonwebtoken::{Algorithm, jwk::JwkSet};
+use serde_json::from_str;
+
+const JWKS: &str = include_str!("../../jwks-sample.json");
+
+#[test]
+fn test_key_index() {
+    let jwks: JwkSet = from_str(JWKS).unwrap();
+    let index = KeyIndex::new(&jwks);
+
+    let jwk = index.get("2011-04-29").unwrap();
+    assert_eq!(jwk.common.key_id.as_deref(), Some("2011-04-29"));
+
+    assert!(index.get("unknown").is_none());
+    assert!(index.find("2011-04-29", Algorithm::RS256).is_some());
+    assert!(
+        index.find("2011-04-29", Algorithm::ES256).is_none(),
+        "Key declares a different algorithm"
+    );
+}
+
+#[test]
+fn test_key_index_without_declared_algorithm() {
+    let jwks: JwkSet = from_str(
+        r#"{"keys": [
+            {"kty": "oct", "kid": "hmac", "k": "c2VjcmV0"},
+            {"kty": "RSA", "kid": "rsa", "n": "sXchDaQebHnPiGvyDOAT4saGEUetSyo9MKLOoWFsueri23bOdgWp4Dy1WlUzewbgBHod5pcM9H95GQRV3JDXboIRROSBigeC5yjU1hGzHHyXss8UDprecbAYxknTcQkhslANGRUZmdTOQ5qTRsLAt6BTYuyvVRdhS8exSZEy_c4gs_7svlJJQ4H9_NxsiIoLwAEk7-Q3UXERGYw_75IDrGA84-lA_-Ct4eTlXHBIY2EaV7t7LjJaynVJCpkv4LKjTTAumiGUIuQhrNhZLuF_RJLqHpM2kgWFLU7-VTdL1VbC2tejvcI2BlMkEpk1BzBZI0KQB0GaDWFLN-aEAw3vRw", "e": "AQAB"}
+        ]}"#,
+    )
+    .unwrap();
+    let index = KeyIndex::new(&jwks);
+
+    assert!(index.find("hmac", Algorithm::HS256).is_some());
+    assert!(index.find("hmac", Algorithm::RS256).is_none());
+    assert!(index.find("rsa", Algorithm::PS512).is_some());
+    assert!(index.find("rsa", Algorithm::EdDSA).is_none());
+}
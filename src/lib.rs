@@ -1,7 +1,27 @@
 mod cache;
+mod circuit_breaker;
+mod clock;
+mod key_index;
+mod negative_cache;
 mod pem_set;
+mod runtime;
+mod state;
 
-pub use cache::TimeoutSpec;
+pub use cache::{
+    AttemptError, CachedJWKSBuilder, ConfigError, DeadlineError, DefaultHttpClient, ErrorClass,
+    HttpError, JwksResponse, JwksSource, KeysFormat, LookupError, ReadyError, RefreshWindow,
+    RefresherHandle, RequestError, RetryPolicy, RetrySchedule, Revalidated, TimeoutSpec, TtlSpec,
+    Validators,
+};
+pub use circuit_breaker::{CircuitBreakerSpec, CircuitState};
+pub use clock::{Clock, ManualClock, SystemClock};
 pub use jsonwebtoken;
+pub use pem_set::CertificateError;
+pub use runtime::Runtime;
+#[cfg(feature = "smol")]
+pub use runtime::SmolRuntime;
+#[cfg(feature = "tokio")]
+pub use runtime::TokioRuntime;
+pub use state::{CachePhase, CacheStatus, JwksSnapshot};
 
-pub type CachedJWKS = cache::CachedJWKS<reqwest::Client>;
+pub type CachedJWKS<S = reqwest::Client> = cache::CachedJWKS<S>;
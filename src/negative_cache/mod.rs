@@ -0,0 +1,46 @@
+#[cfg(test)]
+mod test;
+
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Key IDs which were missing from freshly fetched JWK Set, remembered for a limited time.
+/// Least recently used entries are evicted once capacity is reached
+#[derive(Debug)]
+pub struct NegativeCache {
+    ttl: Duration,
+    entries: Mutex<LruCache<String, Instant>>,
+}
+
+impl NegativeCache {
+    pub fn new(ttl: Duration, capacity: NonZeroUsize) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Key ID was remembered as missing and has not expired yet
+    pub fn contains(&self, kid: &str, now: Instant) -> bool {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match entries.get(kid) {
+            Some(expires) if now < *expires => true,
+            Some(_) => {
+                entries.pop(kid);
+
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&self, kid: &str, now: Instant) {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .put(kid.to_owned(), now + self.ttl);
+    }
+}
@@ -0,0 +1,38 @@
+use super::NegativeCache;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_negative_cache_expiration() {
+    let cache = NegativeCache::new(Duration::from_secs(60), NonZeroUsize::new(10).unwrap());
+    let now = Instant::now();
+
+    assert!(!cache.contains("kid", now));
+
+    cache.insert("kid", now);
+
+    assert!(cache.contains("kid", now));
+    assert!(cache.contains("kid", now + Duration::from_secs(59)));
+    assert!(!cache.contains("kid", now + Duration::from_secs(60)));
+    assert!(
+        !cache.contains("kid", now),
+        "Expired entry should have been removed"
+    );
+}
+
+#[test]
+fn test_negative_cache_capacity() {
+    let cache = NegativeCache::new(Duration::from_secs(60), NonZeroUsize::new(2).unwrap());
+    let now = Instant::now();
+
+    cache.insert("a", now);
+    cache.insert("b", now);
+    assert!(cache.contains("a", now));
+
+    // "b" is least recently used
+    cache.insert("c", now);
+
+    assert!(cache.contains("a", now));
+    assert!(!cache.contains("b", now));
+    assert!(cache.contains("c", now));
+}
@@ -47,51 +47,94 @@ impl<'de> Deserialize<'de> for PemCert {
     }
 }
 
+/// Certificate of a [`PemMap`] which could not be turned into an RSA JWK
+#[derive(Debug, thiserror::Error)]
+pub enum CertificateError {
+    #[error("Certificate {kid} could not be parsed: {reason}")]
+    Parse { kid: String, reason: String },
+    #[error("Certificate {kid} is signed with unsupported algorithm {oid}")]
+    UnsupportedAlgorithm { kid: String, oid: String },
+    #[error("Certificate {kid} does not hold an RSA public key")]
+    NotRsa { kid: String },
+}
+
 #[derive(Deserialize)]
 pub struct PemMap(pub HashMap<String, PemCert>);
 
 impl PemMap {
-    pub fn into_rsa_jwk_set(self) -> JwkSet {
+    /// Certificates which cannot be turned into RSA JWKs are skipped with a warning, the set is
+    /// only rejected when none of them could be.
+    pub fn into_rsa_jwk_set(self) -> Result<JwkSet, CertificateError> {
         let mut parser = X509CertificateParser::new().with_deep_parse_extensions(false);
-
-        let jwks: Vec<Jwk> = self
-            .0
-            .into_iter()
-            .filter_map(|(k, v)| {
-                let Ok((_, cert)) = parser.parse(v.0.as_bytes()) else {
-                    return None;
-                };
-
-                let pkey = cert.public_key();
-
-                let algo = match cert.signature.oid().to_id_string().as_str() {
-                    RS256_OID => KeyAlgorithm::RS256,
-                    RS384_OID => KeyAlgorithm::RS384,
-                    RS512_OID => KeyAlgorithm::RS512,
-                    _ => return None,
-                };
-
-                let Ok(pkey) = pkey.parsed() else { return None };
-                let PublicKey::RSA(rsa_key) = pkey else {
-                    return None;
-                };
-
-                Some(Jwk {
-                    common: CommonParameters {
-                        key_id: Some(k),
-                        key_algorithm: Some(algo),
-                        public_key_use: Some(PublicKeyUse::Signature),
-                        ..Default::default()
-                    },
-                    algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
-                        e: BASE64_URL_SAFE_NO_PAD.encode(rsa_key.exponent),
-                        n: BASE64_URL_SAFE_NO_PAD.encode(rsa_key.modulus),
-                        ..Default::default()
-                    }),
-                })
-            })
-            .collect();
-
-        JwkSet { keys: jwks }
+        let mut keys = Vec::with_capacity(self.0.len());
+        let mut skipped = None;
+
+        for (kid, cert) in self.0 {
+            match rsa_jwk(&mut parser, kid, &cert) {
+                Ok(jwk) => keys.push(jwk),
+                Err(err) => {
+                    log::warn!("Skipping PEM certificate: {err}");
+                    skipped = Some(err);
+                }
+            }
+        }
+
+        match skipped {
+            Some(err) if keys.is_empty() => Err(err),
+            _ => Ok(JwkSet { keys }),
+        }
     }
 }
+
+fn rsa_jwk(
+    parser: &mut X509CertificateParser,
+    kid: String,
+    cert: &PemCert,
+) -> Result<Jwk, CertificateError> {
+    let cert = match parser.parse(cert.0.as_bytes()) {
+        Ok((_, cert)) => cert,
+        Err(err) => {
+            return Err(CertificateError::Parse {
+                kid,
+                reason: err.to_string(),
+            });
+        }
+    };
+
+    let algo = match cert.signature.oid().to_id_string().as_str() {
+        RS256_OID => KeyAlgorithm::RS256,
+        RS384_OID => KeyAlgorithm::RS384,
+        RS512_OID => KeyAlgorithm::RS512,
+        oid => {
+            return Err(CertificateError::UnsupportedAlgorithm {
+                oid: oid.to_owned(),
+                kid,
+            });
+        }
+    };
+
+    let rsa_key = match cert.public_key().parsed() {
+        Ok(PublicKey::RSA(rsa_key)) => rsa_key,
+        Ok(_) => return Err(CertificateError::NotRsa { kid }),
+        Err(err) => {
+            return Err(CertificateError::Parse {
+                kid,
+                reason: err.to_string(),
+            });
+        }
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            key_id: Some(kid),
+            key_algorithm: Some(algo),
+            public_key_use: Some(PublicKeyUse::Signature),
+            ..Default::default()
+        },
+        algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+            e: BASE64_URL_SAFE_NO_PAD.encode(rsa_key.exponent),
+            n: BASE64_URL_SAFE_NO_PAD.encode(rsa_key.modulus),
+            ..Default::default()
+        }),
+    })
+}
@@ -1,15 +1,37 @@
-use super::PemMap;
+use super::{CertificateError, PemMap};
 use serde_json::from_str;
 
 const PKEYS: &str = include_str!("../../publicKeys-sample.json");
 
+const GARBAGE_PEM: &str =
+    "-----BEGIN CERTIFICATE-----\\nZ2FyYmFnZQ==\\n-----END CERTIFICATE-----\\n";
+
 #[test]
 fn test_pem_map() {
     let pem_map: PemMap = from_str(PKEYS).unwrap();
 
     assert_eq!(pem_map.0.len(), 3);
 
-    let jwks = pem_map.into_rsa_jwk_set();
+    let jwks = pem_map.into_rsa_jwk_set().unwrap();
 
     assert_eq!(jwks.keys.len(), 3);
 }
+
+#[test]
+fn test_pem_map_skips_invalid() {
+    let mut pem_map: PemMap = from_str(PKEYS).unwrap();
+    let garbage: PemMap = from_str(&format!(r#"{{"garbage": "{GARBAGE_PEM}"}}"#)).unwrap();
+    pem_map.0.extend(garbage.0);
+
+    let jwks = pem_map.into_rsa_jwk_set().unwrap();
+
+    assert_eq!(jwks.keys.len(), 3);
+    assert!(jwks.find("garbage").is_none());
+
+    let garbage: PemMap = from_str(&format!(r#"{{"garbage": "{GARBAGE_PEM}"}}"#)).unwrap();
+
+    assert!(matches!(
+        garbage.into_rsa_jwk_set(),
+        Err(CertificateError::Parse { kid, .. }) if kid == "garbage"
+    ));
+}
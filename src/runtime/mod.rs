@@ -0,0 +1,305 @@
+#[cfg(all(test, feature = "smol"))]
+mod smol_test;
+#[cfg(all(test, feature = "tokio"))]
+mod test;
+
+use core::future::Future;
+use futures_util::future::{AbortHandle, AbortRegistration, Abortable, BoxFuture, Either};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Executor running background work of the cache, cache itself does not depend on any particular
+/// async runtime
+pub trait Runtime: Send + Sync + 'static {
+    /// Run the task in the background, without awaiting its completion
+    fn spawn(&self, task: BoxFuture<'static, ()>);
+
+    /// Future completing once `duration` has passed
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Runs background work on tokio, either on the runtime current at the time of spawning or on the
+/// one of a given handle
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Default)]
+pub struct TokioRuntime {
+    handle: Option<tokio::runtime::Handle>,
+}
+
+#[cfg(feature = "tokio")]
+impl TokioRuntime {
+    /// Run background work on the runtime of the handle, so the cache can be used outside of it
+    pub fn with_handle(handle: tokio::runtime::Handle) -> Self {
+        Self {
+            handle: Some(handle),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        match &self.handle {
+            Some(handle) => drop(handle.spawn(task)),
+            None => drop(tokio::spawn(task)),
+        }
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        let _enter = self.handle.as_ref().map(tokio::runtime::Handle::enter);
+
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Runs background work on an `async-executor` executor, as used by smol, with `async-io` timers.
+/// The default `reqwest` source needs a tokio reactor, so the cache should be given a [`JwksSource`]
+/// which does not.
+///
+/// [`JwksSource`]: crate::JwksSource
+#[cfg(feature = "smol")]
+#[derive(Debug, Clone)]
+pub struct SmolRuntime {
+    executor: Arc<async_executor::Executor<'static>>,
+}
+
+#[cfg(feature = "smol")]
+impl SmolRuntime {
+    /// Background work is only progressing while the executor is being run
+    pub fn new(executor: Arc<async_executor::Executor<'static>>) -> Self {
+        Self { executor }
+    }
+}
+
+#[cfg(feature = "smol")]
+impl Runtime for SmolRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        self.executor.spawn(task).detach();
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            async_io::Timer::after(duration).await;
+        })
+    }
+}
+
+/// Runtime used unless the cache is given another one, the tokio runtime current at the time of
+/// building the cache if there is one
+pub fn current_runtime() -> Option<Arc<dyn Runtime>> {
+    #[cfg(feature = "tokio")]
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        return Some(Arc::new(TokioRuntime::with_handle(handle)));
+    }
+
+    None
+}
+
+/// Await the future for at most `duration`, `None` when it did not complete in time
+pub async fn timeout<F: Future>(
+    runtime: &dyn Runtime,
+    duration: Duration,
+    future: F,
+) -> Option<F::Output> {
+    let future = core::pin::pin!(future);
+
+    match futures_util::future::select(future, runtime.sleep(duration)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+#[derive(Default)]
+struct TaskSets {
+    /// Fetches and refreshes, which conclude on their own
+    work: HashMap<u64, AbortHandle>,
+    /// Refreshers, which keep running until stopped
+    refreshers: HashMap<u64, AbortHandle>,
+    next_id: u64,
+    shut_down: bool,
+}
+
+/// Background work of the cache, which can be aborted or awaited no matter which runtime runs it
+pub struct BackgroundTasks {
+    sets: Mutex<TaskSets>,
+    /// Number of ongoing fetches and refreshes
+    running: watch::Sender<usize>,
+}
+
+impl Default for BackgroundTasks {
+    fn default() -> Self {
+        Self {
+            sets: Default::default(),
+            running: watch::Sender::new(0),
+        }
+    }
+}
+
+impl core::fmt::Debug for BackgroundTasks {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BackgroundTasks")
+            .field("running", &*self.running.borrow())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Forgets the task no matter how it ends, even if the runtime drops it without completing
+struct TaskGuard {
+    tasks: Arc<BackgroundTasks>,
+    id: u64,
+    refresher: bool,
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        let mut sets = self.tasks.sets();
+
+        if self.refresher {
+            sets.refreshers.remove(&self.id);
+        } else if sets.work.remove(&self.id).is_some() {
+            self.tasks.running.send_modify(|running| *running -= 1);
+        }
+    }
+}
+
+/// Handle of a refresher task
+#[derive(Debug)]
+pub struct RefresherTask {
+    tasks: Arc<BackgroundTasks>,
+    id: u64,
+    abort: AbortHandle,
+}
+
+impl RefresherTask {
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.tasks.sets().refreshers.contains_key(&self.id)
+    }
+}
+
+impl BackgroundTasks {
+    fn sets(&self) -> MutexGuard<'_, TaskSets> {
+        self.sets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn register(
+        self: &Arc<Self>,
+        sets: &mut TaskSets,
+        refresher: bool,
+    ) -> (TaskGuard, AbortHandle, AbortRegistration) {
+        let id = sets.next_id;
+        sets.next_id += 1;
+
+        let (abort, registration) = AbortHandle::new_pair();
+        if refresher {
+            sets.refreshers.insert(id, abort.clone());
+        } else {
+            sets.work.insert(id, abort.clone());
+            self.running.send_modify(|running| *running += 1);
+        }
+
+        let guard = TaskGuard {
+            tasks: self.clone(),
+            id,
+            refresher,
+        };
+
+        (guard, abort, registration)
+    }
+
+    /// Spawn a fetch or refresh, unless the cache was shut down in which case the task is handed back
+    pub fn spawn<F>(self: &Arc<Self>, runtime: &dyn Runtime, task: F) -> Result<(), F>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (guard, _, registration) = {
+            let mut sets = self.sets();
+            if sets.shut_down {
+                return Err(task);
+            }
+
+            self.register(&mut sets, false)
+        };
+
+        runtime.spawn(Box::pin(async move {
+            let _guard = guard;
+            let _ = Abortable::new(task, registration).await;
+        }));
+
+        Ok(())
+    }
+
+    /// Spawn a refresher, unless the cache was shut down
+    pub fn spawn_refresher<F>(
+        self: &Arc<Self>,
+        runtime: &dyn Runtime,
+        task: F,
+    ) -> Option<RefresherTask>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (guard, abort, registration) = {
+            let mut sets = self.sets();
+            if sets.shut_down {
+                return None;
+            }
+
+            self.register(&mut sets, true)
+        };
+        let id = guard.id;
+
+        runtime.spawn(Box::pin(async move {
+            let _guard = guard;
+            let _ = Abortable::new(task, registration).await;
+        }));
+
+        Some(RefresherTask {
+            tasks: self.clone(),
+            id,
+            abort,
+        })
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.sets().shut_down
+    }
+
+    /// Stop taking new tasks, refreshers are aborted right away. Waits up to `deadline` for ongoing
+    /// fetches and refreshes to conclude before aborting them, returns whether they concluded in time.
+    pub async fn shut_down(&self, runtime: &dyn Runtime, deadline: Duration) -> bool {
+        {
+            let mut sets = self.sets();
+            sets.shut_down = true;
+            sets.refreshers
+                .drain()
+                .for_each(|(_, refresher)| refresher.abort());
+        }
+
+        let mut running = self.running.subscribe();
+        let concluded = timeout(runtime, deadline, running.wait_for(|running| *running == 0))
+            .await
+            .is_some();
+
+        if !concluded {
+            // Aborted tasks only conclude once the executor polls them again, which it may never do,
+            // so they are no longer tracked instead of being waited for
+            let mut sets = self.sets();
+            let aborted = sets.work.len();
+            sets.work.drain().for_each(|(_, work)| work.abort());
+            self.running.send_modify(|running| *running -= aborted);
+        }
+
+        concluded
+    }
+
+    pub fn abort_all(&self) {
+        let sets = self.sets();
+        sets.work.values().for_each(AbortHandle::abort);
+        sets.refreshers.values().for_each(AbortHandle::abort);
+    }
+}
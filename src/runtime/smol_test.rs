@@ -0,0 +1,82 @@
+use super::{Runtime, SmolRuntime, timeout};
+use crate::{CachedJWKS, JwksResponse, JwksSource, ManualClock, RefreshWindow};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+const JWKS_SAMPLE: &str = include_str!("../../jwks-sample.json");
+
+/// Source which needs no runtime at all, unlike the default `reqwest` one
+#[derive(Clone, Default)]
+struct StaticSource {
+    fetched: Arc<AtomicUsize>,
+}
+
+impl JwksSource for StaticSource {
+    type Error = ();
+
+    async fn get_jwks(
+        self,
+        _url: url::Url,
+        _as_pkeys: bool,
+        now: SystemTime,
+    ) -> Result<JwksResponse, Self::Error> {
+        self.fetched.fetch_add(1, Ordering::SeqCst);
+
+        Ok(JwksResponse::from((
+            serde_json::from_str(JWKS_SAMPLE).unwrap(),
+            now + Duration::from_secs(100),
+        )))
+    }
+}
+
+#[test]
+fn test_smol_timeout() {
+    let runtime = SmolRuntime::new(Arc::new(async_executor::Executor::new()));
+
+    async_io::block_on(async {
+        assert_eq!(
+            timeout(&runtime, Duration::from_secs(60), async { 1 }).await,
+            Some(1)
+        );
+        assert_eq!(
+            timeout(
+                &runtime,
+                Duration::from_millis(1),
+                runtime.sleep(Duration::from_secs(60))
+            )
+            .await,
+            None
+        );
+    });
+}
+
+#[test]
+fn test_smol_runtime() {
+    let executor = Arc::new(async_executor::Executor::new());
+    let clock = Arc::new(ManualClock::new());
+    let source = StaticSource::default();
+    let cache = CachedJWKS::builder()
+        .url("https://example.com")
+        .refresh_window(RefreshWindow::BeforeExpiry(Duration::from_secs(50)))
+        .source(source.clone())
+        .runtime(SmolRuntime::new(executor.clone()))
+        .clock(clock.clone())
+        .build()
+        .unwrap();
+
+    async_io::block_on(executor.run(async {
+        cache.get().await.unwrap();
+        clock.advance(Duration::from_secs(60));
+        cache.get().await.unwrap();
+
+        // shutting down waits for the background refresh to conclude
+        assert!(cache.shutdown(Duration::from_secs(5)).await);
+        assert_eq!(
+            source.fetched.load(Ordering::SeqCst),
+            2,
+            "Should have refreshed in the background on smol executor"
+        );
+        assert!(!cache.peek().unwrap().is_stale());
+    }));
+}
@@ -0,0 +1,120 @@
+use super::{BackgroundTasks, Runtime, TokioRuntime, timeout};
+use futures_util::future::BoxFuture;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[tokio::test(start_paused = true)]
+async fn test_timeout() {
+    let runtime = TokioRuntime::default();
+
+    assert_eq!(
+        timeout(&runtime, Duration::from_millis(50), async { 1 }).await,
+        Some(1)
+    );
+    assert_eq!(
+        timeout(
+            &runtime,
+            Duration::from_millis(1),
+            runtime.sleep(Duration::from_secs(60))
+        )
+        .await,
+        None
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_shut_down_waits_for_work() {
+    let runtime = TokioRuntime::default();
+    let tasks = Arc::new(BackgroundTasks::default());
+    let completed = Arc::new(AtomicUsize::new(0));
+
+    for _ in 0..3 {
+        let completed = completed.clone();
+        let sleep = runtime.sleep(Duration::from_millis(10));
+        let spawned = tasks.spawn(&runtime, async move {
+            sleep.await;
+            completed.fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(spawned.is_ok());
+    }
+    let refresher = tasks
+        .spawn_refresher(&runtime, runtime.sleep(Duration::from_secs(60)))
+        .unwrap();
+
+    assert!(tasks.shut_down(&runtime, Duration::from_secs(1)).await);
+    assert_eq!(completed.load(Ordering::SeqCst), 3);
+
+    tokio::time::sleep(Duration::from_millis(1)).await;
+    assert!(refresher.is_finished(), "Refresher should be aborted");
+    assert!(
+        tasks.spawn(&runtime, async {}).is_err(),
+        "No work should be taken after shutting down"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_shut_down_aborts_after_deadline() {
+    let runtime = TokioRuntime::default();
+    let tasks = Arc::new(BackgroundTasks::default());
+    let completed = Arc::new(AtomicUsize::new(0));
+
+    let sleep = runtime.sleep(Duration::from_secs(60));
+    let _ = tasks.spawn(&runtime, {
+        let completed = completed.clone();
+        async move {
+            sleep.await;
+            completed.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+
+    assert!(!tasks.shut_down(&runtime, Duration::from_millis(10)).await);
+    assert_eq!(completed.load(Ordering::SeqCst), 0);
+}
+
+/// Executor which is never run, so spawned tasks are never polled
+#[derive(Default)]
+struct IdleRuntime {
+    tasks: Mutex<Vec<BoxFuture<'static, ()>>>,
+}
+
+impl Runtime for IdleRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        self.tasks.lock().unwrap().push(task);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        TokioRuntime::default().sleep(duration)
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_shut_down_idle_executor() {
+    let runtime = IdleRuntime::default();
+    let tasks = Arc::new(BackgroundTasks::default());
+    let _ = tasks.spawn(&runtime, async {});
+    let refresher = tasks.spawn_refresher(&runtime, async {}).unwrap();
+
+    assert!(!tasks.shut_down(&runtime, Duration::from_millis(10)).await);
+    assert!(refresher.is_finished());
+}
+
+#[test]
+fn test_tokio_runtime_with_handle() {
+    let tokio_runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap();
+    let runtime = TokioRuntime::with_handle(tokio_runtime.handle().clone());
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+
+    // spawning and sleeping from outside of the tokio runtime
+    let sleep = runtime.sleep(Duration::from_millis(1));
+    runtime.spawn(Box::pin(async move {
+        sleep.await;
+        done_tx.send(()).unwrap();
+    }));
+
+    done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+}
@@ -0,0 +1,587 @@
+#[cfg(test)]
+mod test;
+
+use super::cache::Validators;
+use super::key_index::KeyIndex;
+use super::negative_cache::NegativeCache;
+use arc_swap::ArcSwap;
+use jsonwebtoken::{
+    Algorithm,
+    jwk::{Jwk, JwkSet},
+};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::watch;
+
+/// JWK Set held by the cache together with its lifetime
+#[derive(Debug, Clone)]
+pub struct CachedSet {
+    pub jwks: Arc<JwkSet>,
+    pub index: Arc<KeyIndex>,
+    /// Key IDs which were looked up, but are missing from this JWK Set
+    pub unknown_kids: Option<Arc<NegativeCache>>,
+    /// Validators for revalidating the JWK Set instead of fetching it again
+    pub validators: Option<Validators>,
+    pub expires: Instant,
+    /// Wall-clock estimate of the expiration, for reporting only
+    pub expires_at: SystemTime,
+    /// When the JWK Set was fetched or last revalidated, for reporting only
+    pub fetched_at: SystemTime,
+    /// Moment from which the JWK Set gets refreshed in the background
+    pub refresh_at: Instant,
+    /// Moment until which the JWK Set can still be served past its expiration while it gets
+    /// refreshed in the background, as allowed by `stale-while-revalidate`
+    pub revalidate_until: Instant,
+    /// Moment until which the JWK Set can still be served when refreshing it fails
+    pub stale_until: Instant,
+}
+
+impl CachedSet {
+    pub fn snapshot(&self, stale: bool) -> JwksSnapshot {
+        JwksSnapshot {
+            jwks: self.jwks.clone(),
+            index: self.index.clone(),
+            unknown_kids: self.unknown_kids.clone(),
+            expires: self.expires_at,
+            stale,
+        }
+    }
+}
+
+/// JWK Set served by the cache
+#[derive(Debug, Clone)]
+pub struct JwksSnapshot {
+    jwks: Arc<JwkSet>,
+    index: Arc<KeyIndex>,
+    unknown_kids: Option<Arc<NegativeCache>>,
+    expires: SystemTime,
+    stale: bool,
+}
+
+impl JwksSnapshot {
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+
+    pub fn into_jwks(self) -> Arc<JwkSet> {
+        self.jwks
+    }
+
+    /// Key with matching key ID
+    pub fn get_key(&self, kid: &str) -> Option<&Arc<Jwk>> {
+        self.index.get(kid)
+    }
+
+    /// Key with matching key ID, which can be used for verifying signatures made with the algorithm
+    pub fn find(&self, kid: &str, alg: Algorithm) -> Option<&Arc<Jwk>> {
+        self.index.find(kid, alg)
+    }
+
+    /// Moment after which the JWK Set is no longer fresh
+    pub fn expires(&self) -> SystemTime {
+        self.expires
+    }
+
+    /// JWK Set is past its expiration, and is served either while it is revalidated within the
+    /// stale-while-revalidate window or because refreshing it failed
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    pub(crate) fn unknown_kids(&self) -> Option<&NegativeCache> {
+        self.unknown_kids.as_deref()
+    }
+}
+
+/// Phase of the cache, see [`CacheStatus`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePhase {
+    /// Nothing is cached and nothing is being fetched
+    Empty,
+    /// Nothing can be served until the ongoing fetch concludes
+    Fetching,
+    /// Cached JWK Set has not expired yet
+    Fresh,
+    /// Cached JWK Set has expired, but can still be served while it gets refreshed
+    Stale,
+    /// Cached JWK Set can no longer be served, it gets fetched again by the next read
+    Expired,
+}
+
+/// Health of the cache as reported by [`CachedJWKS::status`](crate::CachedJWKS::status)
+#[derive(Debug, Clone)]
+pub struct CacheStatus {
+    pub phase: CachePhase,
+    /// When the cached JWK Set was fetched or last revalidated
+    pub fetched_at: Option<SystemTime>,
+    /// When the cached JWK Set expires
+    pub expires: Option<SystemTime>,
+    /// Number of keys in the cached JWK Set
+    pub keys: usize,
+    /// Failed fetches and refreshes since the last successful one
+    pub consecutive_failures: u32,
+    /// Error of the latest failed fetch or refresh, kept after the cache recovers
+    pub last_error: Option<String>,
+    /// Cached JWK Set is being refreshed in the background
+    pub refreshing: bool,
+}
+
+/// State machine of the JWKS cache. Transitions only take the current time as an input and tell
+/// the caller what has to be done, performing the IO is left to the caller.
+#[derive(Debug, Default)]
+pub enum JWKSCache {
+    /// There is no data in cache, this is initial state
+    #[default]
+    Empty,
+    /// Cache is empty or expired, fetching of new content is ongoing.
+    /// Contains handle for awaiting for fetching to conclude, which retains the conclusion
+    /// so it can not be missed by waiters subscribing late, and previous content which can be
+    /// served stale to those who can not wait for the fetch
+    Fetching {
+        done: watch::Receiver<bool>,
+        previous: Option<CachedSet>,
+    },
+    /// Cache can still be served, but content is being refreshed in the background.
+    /// Counts consecutive failed refreshes preceding this one and contains handle for awaiting
+    /// the refresh to conclude
+    Refreshing {
+        cached: CachedSet,
+        failures: u32,
+        done: watch::Receiver<bool>,
+    },
+    /// Cache is populated, but needs to be revalidated before use
+    Fetched(CachedSet),
+    /// Refreshing failed, content is served until it expires (or its stale window runs out)
+    /// while refreshing is retried according to the retry schedule
+    Failing {
+        cached: CachedSet,
+        failures: u32,
+        retry_at: Instant,
+    },
+}
+
+/// What has to be done before the cache can serve a read
+pub enum Decision {
+    /// Serve cached content, refreshing it in the background if asked to
+    Serve {
+        snapshot: JwksSnapshot,
+        refresh: Option<Refresh>,
+    },
+    /// Fetch content, previously cached content can be served stale if fetching fails
+    Fetch(Option<CachedSet>),
+    /// Wait for ongoing fetch to conclude
+    Wait(watch::Receiver<bool>),
+}
+
+/// What a concluded fetch serves to its caller
+#[derive(Debug)]
+pub enum FetchOutcome<E> {
+    /// Source answered with new content
+    Fetched(JwksSnapshot),
+    /// Fetching failed, previous content is served stale instead
+    Stale { snapshot: JwksSnapshot, error: E },
+    /// Fetching failed and there is nothing to serve
+    Failed(E),
+}
+
+impl<E> FetchOutcome<E> {
+    /// Content to serve, which is stale when fetching failed
+    pub fn or_stale(self) -> Result<JwksSnapshot, E> {
+        match self {
+            Self::Fetched(snapshot) | Self::Stale { snapshot, .. } => Ok(snapshot),
+            Self::Failed(error) => Err(error),
+        }
+    }
+
+    /// Content only if the source answered with it
+    pub fn fetched(self) -> Result<JwksSnapshot, E> {
+        match self {
+            Self::Fetched(snapshot) => Ok(snapshot),
+            Self::Stale { error, .. } | Self::Failed(error) => Err(error),
+        }
+    }
+}
+
+/// Background refresh of content which can still be served
+pub struct Refresh {
+    pub cached: CachedSet,
+    /// Consecutive failed refreshes preceding this one
+    pub failures: u32,
+}
+
+impl JWKSCache {
+    pub fn decide(&self, now: Instant) -> Decision {
+        match self {
+            Self::Empty => Decision::Fetch(None),
+            Self::Fetching { done, .. } => Decision::Wait(done.clone()),
+            Self::Refreshing { cached, .. } => {
+                if now >= cached.stale_until {
+                    // Background refresh did not conclude in time, content can no longer be served
+                    Decision::Fetch(None)
+                } else {
+                    Decision::Serve {
+                        snapshot: cached.snapshot(now >= cached.expires),
+                        refresh: None,
+                    }
+                }
+            }
+            Self::Fetched(cached) => {
+                if now >= cached.revalidate_until {
+                    Decision::Fetch(Some(cached.clone()))
+                } else {
+                    Decision::Serve {
+                        snapshot: cached.snapshot(now >= cached.expires),
+                        refresh: (now >= cached.refresh_at).then(|| Refresh {
+                            cached: cached.clone(),
+                            failures: 0,
+                        }),
+                    }
+                }
+            }
+            Self::Failing {
+                cached,
+                failures,
+                retry_at,
+            } => {
+                if now >= cached.stale_until {
+                    // Content can no longer be served, only freshly fetched content will do
+                    Decision::Fetch(None)
+                } else {
+                    Decision::Serve {
+                        snapshot: cached.snapshot(now >= cached.expires),
+                        refresh: (now >= *retry_at).then(|| Refresh {
+                            cached: cached.clone(),
+                            failures: *failures,
+                        }),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Content which is still held by the cache, regardless of whether it can be served
+    pub fn cached(&self) -> Option<&CachedSet> {
+        match self {
+            Self::Refreshing { cached, .. }
+            | Self::Fetched(cached)
+            | Self::Failing { cached, .. } => Some(cached),
+            Self::Empty | Self::Fetching { .. } => None,
+        }
+    }
+
+    /// Content held by the cache which can still be served at `now`, flagged stale once expired.
+    /// While fetching, previous content is served for as long as it is allowed to be stale.
+    pub fn peek(&self, now: Instant) -> Option<JwksSnapshot> {
+        let cached = match self {
+            Self::Fetching { previous, .. } => previous.as_ref(),
+            _ => self.cached(),
+        };
+
+        cached
+            .filter(|cached| now < cached.stale_until)
+            .map(|cached| cached.snapshot(now >= cached.expires))
+    }
+
+    /// Phase of the cache at `now` as reported in its status
+    pub fn phase(&self, now: Instant) -> CachePhase {
+        let cached = match self {
+            Self::Empty => return CachePhase::Empty,
+            Self::Fetching { .. } => return CachePhase::Fetching,
+            Self::Fetched(cached) if now >= cached.revalidate_until => return CachePhase::Expired,
+            Self::Refreshing { cached, .. }
+            | Self::Fetched(cached)
+            | Self::Failing { cached, .. } => cached,
+        };
+
+        if now < cached.expires {
+            CachePhase::Fresh
+        } else if now < cached.stale_until {
+            CachePhase::Stale
+        } else {
+            CachePhase::Expired
+        }
+    }
+
+    /// State after a fetch concluded, together with what to serve to its callers. If fetching failed,
+    /// previous content is served for as long as it is allowed to be stale, while refreshing it is
+    /// retried after `retry_delay`.
+    pub fn after_fetch<E: core::fmt::Debug>(
+        now: Instant,
+        previous: Option<CachedSet>,
+        result: Result<CachedSet, E>,
+        retry_delay: Duration,
+    ) -> (Self, FetchOutcome<E>) {
+        match result {
+            Ok(cached) => {
+                let snapshot = cached.snapshot(false);
+
+                (Self::Fetched(cached), FetchOutcome::Fetched(snapshot))
+            }
+            Err(err) => match previous {
+                // Source is failing, keep serving previous content for as long as it is allowed to be stale
+                Some(previous) if now < previous.stale_until => {
+                    log::warn!("Serving stale JWKS, fetching failed: {err:?}");
+
+                    let snapshot = previous.snapshot(now >= previous.expires);
+
+                    (
+                        Self::Failing {
+                            cached: previous,
+                            failures: 1,
+                            retry_at: now + retry_delay,
+                        },
+                        FetchOutcome::Stale {
+                            snapshot,
+                            error: err,
+                        },
+                    )
+                }
+                // Could not fetch in time, let follow up request try again later
+                _ => (Self::Empty, FetchOutcome::Failed(err)),
+            },
+        }
+    }
+
+    /// State after a background refresh concluded, `None` when the state was concluded by someone
+    /// else in the meantime, so it is no longer the refresh waited for by `done`. Failed refresh is
+    /// retried after delay for the number of consecutive failures.
+    pub fn after_refresh(
+        &self,
+        now: Instant,
+        done: &watch::Sender<bool>,
+        result: Option<CachedSet>,
+        retry_delay: impl FnOnce(u32) -> Duration,
+    ) -> Option<Self> {
+        let Self::Refreshing {
+            cached,
+            failures,
+            done: waiters,
+        } = self
+        else {
+            return None;
+        };
+
+        // Content fetched by someone else in the meantime must not be overwritten by an older one
+        if !waiters.same_channel(&done.subscribe()) {
+            return None;
+        }
+
+        Some(match result {
+            Some(cached) => Self::Fetched(cached),
+            None => {
+                let failures = failures + 1;
+
+                Self::Failing {
+                    cached: cached.clone(),
+                    failures,
+                    retry_at: now + retry_delay(failures),
+                }
+            }
+        })
+    }
+}
+
+/// Current state of the JWKS cache. Readers load immutable state without any locking,
+/// while transitions to a new state are serialized by the write lock
+#[derive(Default)]
+pub struct CacheState {
+    current: ArcSwap<JWKSCache>,
+    write: Mutex<()>,
+    /// When was the last refresh forced by a lookup of unknown key ID
+    forced_refresh_at: Mutex<Option<Instant>>,
+    /// Outcomes of fetches and refreshes, for reporting only
+    outcomes: Mutex<Outcomes>,
+}
+
+#[derive(Default)]
+struct Outcomes {
+    consecutive_failures: u32,
+    last_error: Option<String>,
+}
+
+impl CacheState {
+    /// Lock is only held for swapping the state and never across await points
+    fn write(&self) -> MutexGuard<'_, ()> {
+        self.write.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn load(&self) -> arc_swap::Guard<Arc<JWKSCache>> {
+        self.current.load()
+    }
+
+    pub fn load_full(&self) -> Arc<JWKSCache> {
+        self.current.load_full()
+    }
+
+    pub fn store(&self, state: JWKSCache) {
+        let _write = self.write();
+
+        self.current.store(Arc::new(state));
+    }
+
+    /// Claim a forced refresh, unless the last one was less than `min_interval` ago. Claimed refresh
+    /// is started by `start` while the claim is still held, so that callers denied it find it in
+    /// flight.
+    pub fn claim_forced_refresh<T>(
+        &self,
+        now: Instant,
+        min_interval: Duration,
+        start: impl FnOnce() -> T,
+    ) -> Option<T> {
+        let mut forced_refresh_at = self
+            .forced_refresh_at
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        match *forced_refresh_at {
+            Some(at) if now < at + min_interval => None,
+            _ => {
+                *forced_refresh_at = Some(now);
+
+                Some(start())
+            }
+        }
+    }
+
+    /// Record outcome of a fetch or refresh, `error` is `None` when it succeeded
+    pub fn record_outcome(&self, error: Option<String>) {
+        let mut outcomes = self.outcomes.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match error {
+            Some(error) => {
+                outcomes.consecutive_failures += 1;
+                outcomes.last_error = Some(error);
+            }
+            None => outcomes.consecutive_failures = 0,
+        }
+    }
+
+    pub fn status(&self, now: Instant) -> CacheStatus {
+        let current = self.current.load();
+        let cached = current.cached();
+        let outcomes = self.outcomes.lock().unwrap_or_else(PoisonError::into_inner);
+
+        CacheStatus {
+            phase: current.phase(now),
+            fetched_at: cached.map(|cached| cached.fetched_at),
+            expires: cached.map(|cached| cached.expires_at),
+            keys: cached.map_or(0, |cached| cached.jwks.keys.len()),
+            consecutive_failures: outcomes.consecutive_failures,
+            last_error: outcomes.last_error.clone(),
+            refreshing: matches!(**current, JWKSCache::Refreshing { .. }),
+        }
+    }
+
+    /// Become the one fetching new content, unless someone else already is. Content `previous` to
+    /// the fetch is retained while fetching.
+    pub fn elect(
+        self: &Arc<Self>,
+        observed: &Arc<JWKSCache>,
+        previous: Option<CachedSet>,
+    ) -> Election {
+        let _write = self.write();
+        let current = self.current.load();
+
+        if let JWKSCache::Fetching { done, .. } = &**current {
+            return Election::Follower(done.clone());
+        }
+
+        if !Arc::ptr_eq(&current, observed) {
+            return Election::Outdated;
+        }
+
+        let (done, waiters) = watch::channel(false);
+
+        self.current.store(Arc::new(JWKSCache::Fetching {
+            done: waiters,
+            previous,
+        }));
+
+        Election::Leader(FetchGuard {
+            cache_state: self.clone(),
+            done,
+        })
+    }
+
+    /// Start refreshing in the background, unless the state has moved on since it was observed.
+    /// Waiters of the refresh are released once the returned sender gets dropped.
+    pub fn begin_refresh(
+        &self,
+        observed: &Arc<JWKSCache>,
+        refresh: Refresh,
+    ) -> Option<watch::Sender<bool>> {
+        let _write = self.write();
+
+        // Someone else has already moved the state on
+        if !Arc::ptr_eq(&self.current.load(), observed) {
+            return None;
+        }
+
+        let (done, waiters) = watch::channel(false);
+
+        self.current.store(Arc::new(JWKSCache::Refreshing {
+            cached: refresh.cached,
+            failures: refresh.failures,
+            done: waiters,
+        }));
+
+        Some(done)
+    }
+
+    /// Conclude background refresh, see [`JWKSCache::after_refresh`]
+    pub fn conclude_refresh(
+        &self,
+        now: Instant,
+        done: &watch::Sender<bool>,
+        result: Option<CachedSet>,
+        retry_delay: impl FnOnce(u32) -> Duration,
+    ) {
+        let _write = self.write();
+
+        if let Some(new_state) = self
+            .current
+            .load()
+            .after_refresh(now, done, result, retry_delay)
+        {
+            self.current.store(Arc::new(new_state));
+        }
+    }
+}
+
+/// Wait for fetch to conclude, sender is only dropped once fetch has concluded so an error means the same
+pub async fn concluded(mut waiters: watch::Receiver<bool>) {
+    let _ = waiters.wait_for(|done| *done).await;
+}
+
+/// Outcome of attempting to start fetching new content
+pub enum Election {
+    /// Caller has to perform the fetch and conclude it
+    Leader(FetchGuard),
+    /// Fetch is already ongoing, caller has to wait for it to conclude
+    Follower(watch::Receiver<bool>),
+    /// State has moved on since it was observed and has to be evaluated again
+    Outdated,
+}
+
+/// Releases waiters of a fetch no matter how it ends, if fetch did not conclude the state
+/// (it panicked, got aborted or was dropped without ever being run) the cache is reset so
+/// follow up requests can try again
+pub struct FetchGuard {
+    cache_state: Arc<CacheState>,
+    done: watch::Sender<bool>,
+}
+
+impl Drop for FetchGuard {
+    fn drop(&mut self) {
+        {
+            let _write = self.cache_state.write();
+
+            if matches!(&**self.cache_state.current.load(), JWKSCache::Fetching { done, .. } if done.same_channel(&self.done.subscribe()))
+            {
+                self.cache_state.current.store(Default::default());
+            }
+        }
+
+        self.done.send_replace(true);
+    }
+}
@@ -0,0 +1,227 @@
+use super::{CachePhase, CacheState, CachedSet, Decision, Election, FetchOutcome, JWKSCache};
+use crate::key_index::KeyIndex;
+use jsonwebtoken::jwk::JwkSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+fn cached_set(now: Instant) -> CachedSet {
+    CachedSet {
+        jwks: Arc::new(JwkSet { keys: Vec::new() }),
+        index: Arc::new(KeyIndex::default()),
+        unknown_kids: None,
+        validators: None,
+        expires: now + Duration::from_secs(100),
+        expires_at: SystemTime::now() + Duration::from_secs(100),
+        fetched_at: SystemTime::now(),
+        refresh_at: now + Duration::from_secs(80),
+        revalidate_until: now + Duration::from_secs(100),
+        stale_until: now + Duration::from_secs(200),
+    }
+}
+
+#[test]
+fn test_decide_fetched() {
+    let now = Instant::now();
+    let state = JWKSCache::Fetched(cached_set(now));
+
+    assert!(matches!(
+        state.decide(now),
+        Decision::Serve { snapshot, refresh: None } if !snapshot.is_stale()
+    ));
+    assert!(matches!(
+        state.decide(now + Duration::from_secs(80)),
+        Decision::Serve { refresh: Some(refresh), .. } if refresh.failures == 0
+    ));
+    assert!(matches!(
+        state.decide(now + Duration::from_secs(100)),
+        Decision::Fetch(Some(_))
+    ));
+}
+
+#[test]
+fn test_decide_stale_while_revalidate() {
+    let now = Instant::now();
+    let state = JWKSCache::Fetched(CachedSet {
+        refresh_at: now + Duration::from_secs(100),
+        revalidate_until: now + Duration::from_secs(150),
+        ..cached_set(now)
+    });
+
+    assert!(matches!(
+        state.decide(now + Duration::from_secs(80)),
+        Decision::Serve { refresh: None, .. }
+    ));
+    assert!(matches!(
+        state.decide(now + Duration::from_secs(120)),
+        Decision::Serve { snapshot, refresh: Some(_) } if snapshot.is_stale()
+    ));
+    assert!(matches!(
+        state.decide(now + Duration::from_secs(150)),
+        Decision::Fetch(Some(_))
+    ));
+}
+
+#[test]
+fn test_decide_failing() {
+    let now = Instant::now();
+    let state = JWKSCache::Failing {
+        cached: cached_set(now),
+        failures: 2,
+        retry_at: now + Duration::from_secs(120),
+    };
+
+    assert!(matches!(
+        state.decide(now + Duration::from_secs(110)),
+        Decision::Serve { snapshot, refresh: None } if snapshot.is_stale()
+    ));
+    assert!(matches!(
+        state.decide(now + Duration::from_secs(120)),
+        Decision::Serve { refresh: Some(refresh), .. } if refresh.failures == 2
+    ));
+    assert!(matches!(
+        state.decide(now + Duration::from_secs(200)),
+        Decision::Fetch(None)
+    ));
+}
+
+#[test]
+fn test_phase() {
+    let now = Instant::now();
+    let fetched = JWKSCache::Fetched(cached_set(now));
+    let failing = JWKSCache::Failing {
+        cached: cached_set(now),
+        failures: 1,
+        retry_at: now,
+    };
+
+    assert_eq!(JWKSCache::Empty.phase(now), CachePhase::Empty);
+    assert_eq!(fetched.phase(now), CachePhase::Fresh);
+    assert_eq!(
+        fetched.phase(now + Duration::from_secs(100)),
+        CachePhase::Expired
+    );
+    assert_eq!(
+        failing.phase(now + Duration::from_secs(100)),
+        CachePhase::Stale
+    );
+    assert_eq!(
+        failing.phase(now + Duration::from_secs(200)),
+        CachePhase::Expired
+    );
+}
+
+#[test]
+fn test_after_fetch() {
+    let now = Instant::now();
+    let retry_delay = Duration::from_secs(5);
+
+    let (state, outcome) =
+        JWKSCache::after_fetch(now, None, Ok::<_, ()>(cached_set(now)), retry_delay);
+    assert!(matches!(state, JWKSCache::Fetched(_)));
+    assert!(matches!(outcome, FetchOutcome::Fetched(snapshot) if !snapshot.is_stale()));
+
+    let (state, outcome) = JWKSCache::after_fetch(now, None, Err(()), retry_delay);
+    assert!(matches!(state, JWKSCache::Empty));
+    assert!(matches!(outcome, FetchOutcome::Failed(())));
+
+    let later = now + Duration::from_secs(150);
+    let (state, outcome) =
+        JWKSCache::after_fetch(later, Some(cached_set(now)), Err(()), retry_delay);
+    assert!(matches!(
+        state,
+        JWKSCache::Failing { failures: 1, retry_at, .. } if retry_at == later + retry_delay
+    ));
+    assert!(matches!(
+        outcome,
+        FetchOutcome::Stale { ref snapshot, .. } if snapshot.is_stale()
+    ));
+    assert!(outcome.fetched().is_err());
+
+    let expired = now + Duration::from_secs(200);
+    let (state, outcome) =
+        JWKSCache::after_fetch(expired, Some(cached_set(now)), Err(()), retry_delay);
+    assert!(matches!(state, JWKSCache::Empty));
+    assert!(outcome.or_stale().is_err());
+}
+
+#[test]
+fn test_after_refresh() {
+    let now = Instant::now();
+    let (done, waiters) = tokio::sync::watch::channel(false);
+    let state = JWKSCache::Refreshing {
+        cached: cached_set(now),
+        failures: 2,
+        done: waiters,
+    };
+
+    assert!(matches!(
+        state.after_refresh(now, &done, Some(cached_set(now)), |_| unreachable!()),
+        Some(JWKSCache::Fetched(_))
+    ));
+    assert!(matches!(
+        state.after_refresh(now, &done, None, |failures| Duration::from_secs(failures.into())),
+        Some(JWKSCache::Failing { failures: 3, retry_at, .. }) if retry_at == now + Duration::from_secs(3)
+    ));
+    assert!(
+        JWKSCache::Empty
+            .after_refresh(now, &done, None, |_| unreachable!())
+            .is_none(),
+        "State concluded by someone else should be kept"
+    );
+
+    let (older, _) = tokio::sync::watch::channel(false);
+    assert!(
+        state
+            .after_refresh(now, &older, Some(cached_set(now)), |_| unreachable!())
+            .is_none(),
+        "Older refresh should not overwrite the state of a newer one"
+    );
+    assert!(
+        JWKSCache::Fetched(cached_set(now))
+            .after_refresh(now, &done, Some(cached_set(now)), |_| unreachable!())
+            .is_none(),
+        "Content fetched in the meantime should be kept"
+    );
+}
+
+#[test]
+fn test_election() {
+    let cache_state = Arc::new(CacheState::default());
+    let observed = cache_state.load_full();
+
+    let Election::Leader(guard) = cache_state.elect(&observed, None) else {
+        panic!("Expected to lead the fetch");
+    };
+    assert!(matches!(
+        cache_state.elect(&observed, None),
+        Election::Follower(_)
+    ));
+
+    // fetch dropped without concluding the state resets it
+    drop(guard);
+    assert!(matches!(*cache_state.load_full(), JWKSCache::Empty));
+    assert!(matches!(
+        cache_state.elect(&observed, None),
+        Election::Outdated
+    ));
+}
+
+#[test]
+fn test_peek_while_fetching() {
+    let now = Instant::now();
+    let cache_state = Arc::new(CacheState::default());
+    cache_state.store(JWKSCache::Fetched(cached_set(now)));
+    let observed = cache_state.load_full();
+
+    let Election::Leader(_guard) = cache_state.elect(&observed, observed.cached().cloned()) else {
+        panic!("Expected to lead the fetch");
+    };
+    let fetching = cache_state.load_full();
+
+    assert!(fetching.cached().is_none());
+    assert!(matches!(
+        fetching.peek(now + Duration::from_secs(150)),
+        Some(snapshot) if snapshot.is_stale()
+    ));
+    assert!(fetching.peek(now + Duration::from_secs(200)).is_none());
+}